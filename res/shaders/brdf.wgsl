@@ -1,3 +1,7 @@
+#pragma once
+
+#include constants.wgsl
+
 // normal distribution function (Trowbridge-Reitz GGX)
 
 fn distribution_ggx(n: vec3<f32>, h: vec3<f32>, a: f32) -> f32 {
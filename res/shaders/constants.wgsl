@@ -1,18 +1,11 @@
+#pragma once
+
 const PI = 3.14159;
 const INV_SQRT_2 = 0.70710678118654752440; // 1 / sqrt(2)
 const INV_SQRT_3 = 0.57735026918962576451; // 1 / sqrt(3)
 
-// in every direction from 0,0
-// total = (2n + 1)^2
-const SHADOW_SAMPLES = 2;
-const INV_SHADOW_SAMPLES = 1.0 / 25.0;
+// SHADOW_MAP_SIZE, SHADOW_SAMPLES, INV_SHADOW_SAMPLES and the FOG_ step,
+// density & alpha values are defined by the renderer, see ShaderSettings.
 
-const FOG_MAX_STEPS = 30;
-const FOG_STEP_SIZE = 5.0;
-const FOG_LIGHT_MAX_STEPS = 10;
-const FOG_LIGHT_STEP_SIZE = 10.0;
-const FOG_DENSITY = 2.0;
-const FOG_LIGHT_DENSITY = 3.0;
-const FOG_ALPHA = 1.0;
 const FOG_DENSITY_COLOR = vec2<f32>(0.5, 0.1);
 
@@ -1,10 +1,15 @@
 #include globals.wgsl
 
+struct DepthOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+}
+
 @vertex
 fn vs_main(
     model: VertexInput,
     instance: InstanceInput,
-) -> @builtin(position) vec4<f32> {
+) -> DepthOutput {
     let model_matrix = mat4x4<f32>(
         instance.model_matrix_0,
         instance.model_matrix_1,
@@ -13,5 +18,27 @@ fn vs_main(
     );
 
     let world_position = model_matrix * vec4<f32>(model.position, 1.0);
-    return light.matrices[global_uniforms.light_matrix_index] * world_position;
+
+    var out: DepthOutput;
+    out.clip_position = light.matrices[global_uniforms.light_matrix_index] * world_position;
+    out.tex_coords = model.tex_coords;
+    return out;
+}
+
+@group(1) @binding(0)
+var t_diffuse: texture_2d<f32>;
+@group(1) @binding(1)
+var s_diffuse: sampler;
+
+@group(1) @binding(6)
+var<uniform> material_uniform: MaterialUniform;
+
+@fragment
+fn fs_main(vert: DepthOutput) {
+#ifdef ALPHA_MASK
+    let alpha = textureSample(t_diffuse, s_diffuse, vert.tex_coords).a * material_uniform.base_color_factor.a;
+    if (alpha < material_uniform.alpha_cutoff) {
+        discard;
+    }
+#endif
 }
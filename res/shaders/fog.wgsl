@@ -40,10 +40,9 @@ var t_light_depth: texture_depth_2d_array;
 @group(1) @binding(1)
 var s_light_depth: sampler_comparison;
 
+// bound as float since GL can't read depth textures without comparison
 @group(2) @binding(0)
-var t_geometry_depth: texture_depth_2d;
-@group(2) @binding(1)
-var s_geometry_depth: sampler;
+var t_geometry_depth: texture_2d<f32>;
 
 fn fog_noise(pos: vec3<f32>) -> f32 {
     var p1 = pos * 0.01;
@@ -127,8 +126,8 @@ fn fs_main(vert: FogVertexOutput) -> @location(0) vec4<f32> {
     let origin = vert.world_position.xyz;
     let direction = normalize(origin - camera.position.xyz);
     let volume_depth = depth_to_linear(vert.clip_position.z);
-    let uv = vert.clip_position.xy / camera.planes.zw;
-    let geometry_depth = depth_to_linear(textureSample(t_geometry_depth, s_geometry_depth, uv));
+    let pixel = vec2<i32>(vert.clip_position.xy);
+    let geometry_depth = depth_to_linear(textureLoad(t_geometry_depth, pixel, 0).r);
     let max_fog_depth = geometry_depth - volume_depth;
     if (max_fog_depth <= 0.0)
     {
@@ -0,0 +1,29 @@
+// Downsamples the previous mip level with a single bilinear tap. The source
+// view only has that one level, so sample it explicitly.
+
+struct MipmapVertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+};
+
+// fullscreen triangle
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vertex_index: u32,
+) -> MipmapVertexOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    var out: MipmapVertexOutput;
+    out.clip_position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.tex_coords = uv;
+    return out;
+}
+
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+@group(0) @binding(1)
+var s_source: sampler;
+
+@fragment
+fn fs_main(in: MipmapVertexOutput) -> @location(0) vec4<f32> {
+    return textureSampleLevel(t_source, s_source, in.tex_coords, 0.0);
+}
@@ -1,3 +1,5 @@
+#pragma once
+
 // Noise functions from 42yeah:
 // https://blog.42yeah.is/rendering/2023/02/11/clouds.html
 fn rand(p: vec3<f32>) -> f32 {
@@ -22,9 +22,16 @@ fn vs_main(
         instance.normal_matrix_2,
     );
 
+    let model_matrix_3x3 = mat3x3<f32>(
+        model_matrix[0].xyz,
+        model_matrix[1].xyz,
+        model_matrix[2].xyz,
+    );
     let world_normal = normalize(normal_matrix * model.normal);
-    let world_tangent = normalize(normal_matrix * model.tangent);
-    let world_bitangent = normalize(normal_matrix * model.bitangent);
+    let world_tangent = normalize(model_matrix_3x3 * model.tangent.xyz);
+    // MikkTSpace bitangent, flipped again if the instance is mirrored
+    let bitangent_sign = model.tangent.w * sign(determinant(model_matrix_3x3));
+    let world_bitangent = cross(world_normal, world_tangent) * bitangent_sign;
     let tangent_matrix = transpose(mat3x3<f32>(
         world_tangent,
         world_bitangent,
@@ -70,21 +77,44 @@ var s_roughness_metalness: sampler;
 @group(2) @binding(6)
 var<uniform> material_uniform: MaterialUniform;
 
+@group(2) @binding(7)
+var t_emissive: texture_2d<f32>;
+@group(2) @binding(8)
+var s_emissive: sampler;
+
+@group(2) @binding(9)
+var t_occlusion: texture_2d<f32>;
+@group(2) @binding(10)
+var s_occlusion: sampler;
+
 @fragment
-fn fs_main(vert: VertexOutput) -> @location(0) vec4<f32> {
+fn fs_main(vert: VertexOutput, @builtin(front_facing) front_facing: bool) -> @location(0) vec4<f32> {
     // textures
     let tex_diffuse: vec4<f32> = textureSample(t_diffuse, s_diffuse, vert.tex_coords);
     let tex_normal: vec4<f32> = textureSample(t_normal, s_normal, vert.tex_coords);
     let tex_rm: vec4<f32> = textureSample(
         t_roughness_metalness, s_roughness_metalness, vert.tex_coords);
+    let tex_emissive: vec4<f32> = textureSample(t_emissive, s_emissive, vert.tex_coords);
+    let tex_occlusion: vec4<f32> = textureSample(t_occlusion, s_occlusion, vert.tex_coords);
 
-    let albedo = tex_diffuse.rgb;
+    let base_color = tex_diffuse * material_uniform.base_color_factor;
+#ifdef ALPHA_MASK
+    if (base_color.a < material_uniform.alpha_cutoff) {
+        discard;
+    }
+#endif
+    let albedo = base_color.rgb;
     let roughness = tex_rm.g * material_uniform.roughness_factor;
     let metalness = tex_rm.b * material_uniform.metallic_factor;
 
     var total_radiance: vec3<f32>;
 
-    let normal_dir = tex_normal.xyz * 2.0 - 1.0;
+    let normal_scale = vec3(material_uniform.normal_scale, material_uniform.normal_scale, 1.0);
+    var normal_dir = normalize((tex_normal.xyz * 2.0 - 1.0) * normal_scale);
+    // back faces of double sided materials
+    if (!front_facing) {
+        normal_dir = -normal_dir;
+    }
     var light_dir = normalize(vert.tangent_light_position - vert.tangent_position);
     let surface_light_dot = dot(normal_dir, light_dir);
     let light_dist = length(light.position - vert.world_position.xyz);
@@ -120,11 +150,19 @@ fn fs_main(vert: VertexOutput) -> @location(0) vec4<f32> {
 
     var ambient = sample_ambient_light(light.color, light_dist, surface_light_dot);
     ambient *= albedo;
+    ambient *= mix(1.0, tex_occlusion.r, material_uniform.occlusion_strength);
+
+    let emissive = tex_emissive.rgb * material_uniform.emissive_factor;
 
-    var result = ambient + total_radiance;
+    var result = ambient + total_radiance + emissive;
 
     // tonemap
     result = result / (result + vec3(1.0));
 
-    return vec4<f32>(result, tex_diffuse.a);
+    // opaque materials are opaque whatever their base color alpha is
+#if defined(ALPHA_MASK) || defined(ALPHA_BLEND)
+    return vec4<f32>(result, base_color.a);
+#else
+    return vec4<f32>(result, 1.0);
+#endif
 }
@@ -2,4 +2,7 @@
 
 wasm-pack build --debug --target web
 sed 's/.\/pkg/./g' index.html > pkg/index.html
+# scenes & models are fetched from next to the page
+rm -rf pkg/res
+cp -r res pkg/res
 miniserve pkg --index index.html
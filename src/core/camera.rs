@@ -5,6 +5,8 @@ use cgmath::SquareMatrix;
 use winit::{dpi::PhysicalPosition, event::*};
 use winit::keyboard::{PhysicalKey, KeyCode};
 
+use crate::shaders::structs::{wgsl_struct, Usage};
+
 pub const NEAR_PLANE: f32 = 1.0;
 pub const FAR_PLANE: f32 = 3000.0;
 
@@ -111,6 +113,8 @@ pub struct CameraUniform {
     pub planes: [f32; 4],
 }
 
+wgsl_struct!(CameraUniform, Usage::Uniform, [view, proj, inv_view_proj, position, planes]);
+
 impl CameraUniform {
     pub fn new() -> Self {
         Self {
@@ -134,6 +138,12 @@ impl CameraUniform {
     }
 }
 
+impl Default for CameraUniform {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub struct CameraController {
     pub speed: f32,
     pub sensitivity: f32,
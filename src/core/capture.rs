@@ -0,0 +1,202 @@
+use std::io::Cursor;
+use std::path::Path;
+use std::sync::mpsc;
+
+use anyhow::*;
+
+/// A texture copied back to the CPU, with the row padding removed.
+pub struct Capture {
+    pub width: u32,
+    pub height: u32,
+    pub format: wgpu::TextureFormat,
+    pub data: Vec<u8>,
+}
+
+impl Capture {
+    /// Copies the first mip & layer of `texture` into a buffer and reads it back.
+    /// Blocks until the GPU is done.
+    pub fn read_texture(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture: &wgpu::Texture,
+        aspect: wgpu::TextureAspect,
+    ) -> Result<Self> {
+        let width = texture.width();
+        let height = texture.height();
+        let format = texture.format();
+        let bytes_per_pixel = format
+            .block_size(Some(aspect))
+            .with_context(|| format!("capture: can't read back format {:?}", format))?;
+
+        let unpadded_bytes_per_row = width * bytes_per_pixel;
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(align) * align;
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Capture Buffer"),
+            size: (padded_bytes_per_row * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Capture Encoder"),
+        });
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                aspect,
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = buffer.slice(..);
+        let (sender, receiver) = mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            sender.send(result).ok();
+        });
+        device.poll(wgpu::Maintain::Wait);
+        receiver.recv()??;
+
+        let mut data = Vec::with_capacity((unpadded_bytes_per_row * height) as usize);
+        {
+            let mapped = slice.get_mapped_range();
+            for row in mapped.chunks(padded_bytes_per_row as usize) {
+                data.extend_from_slice(&row[..unpadded_bytes_per_row as usize]);
+            }
+        }
+        buffer.unmap();
+
+        Ok(Self {
+            width,
+            height,
+            format,
+            data,
+        })
+    }
+
+    /// Float formats are written as EXR, everything else as PNG.
+    pub fn is_hdr(&self) -> bool {
+        matches!(
+            self.format,
+            wgpu::TextureFormat::Rgba16Float
+                | wgpu::TextureFormat::Rgba32Float
+                | wgpu::TextureFormat::R32Float
+                | wgpu::TextureFormat::Depth32Float
+        )
+    }
+
+    pub fn file_extension(&self) -> &'static str {
+        if self.is_hdr() { "exr" } else { "png" }
+    }
+
+    pub fn to_image(&self) -> Result<image::DynamicImage> {
+        let size_error = || anyhow!("capture: data doesn't match size {}x{}", self.width, self.height);
+        let image = match self.format {
+            wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => {
+                image::RgbaImage::from_raw(self.width, self.height, self.data.clone())
+                    .map(image::DynamicImage::ImageRgba8)
+                    .ok_or_else(size_error)?
+            }
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => {
+                let pixels = self.data
+                    .chunks_exact(4)
+                    .flat_map(|s| [s[2], s[1], s[0], s[3]])
+                    .collect();
+                image::RgbaImage::from_raw(self.width, self.height, pixels)
+                    .map(image::DynamicImage::ImageRgba8)
+                    .ok_or_else(size_error)?
+            }
+            wgpu::TextureFormat::Rgba16Float => {
+                let pixels = self.data
+                    .chunks_exact(2)
+                    .map(|s| f16_to_f32(u16::from_le_bytes([s[0], s[1]])))
+                    .collect();
+                image::Rgba32FImage::from_raw(self.width, self.height, pixels)
+                    .map(image::DynamicImage::ImageRgba32F)
+                    .ok_or_else(size_error)?
+            }
+            wgpu::TextureFormat::Rgba32Float => {
+                let pixels = self.data
+                    .chunks_exact(4)
+                    .map(|s| f32::from_le_bytes([s[0], s[1], s[2], s[3]]))
+                    .collect();
+                image::Rgba32FImage::from_raw(self.width, self.height, pixels)
+                    .map(image::DynamicImage::ImageRgba32F)
+                    .ok_or_else(size_error)?
+            }
+            wgpu::TextureFormat::R32Float | wgpu::TextureFormat::Depth32Float => {
+                let pixels = self.data
+                    .chunks_exact(4)
+                    .map(|s| f32::from_le_bytes([s[0], s[1], s[2], s[3]]))
+                    .flat_map(|v| [v, v, v])
+                    .collect();
+                image::Rgb32FImage::from_raw(self.width, self.height, pixels)
+                    .map(image::DynamicImage::ImageRgb32F)
+                    .ok_or_else(size_error)?
+            }
+            format => bail!("capture: unsupported format {:?}", format),
+        };
+
+        Ok(image)
+    }
+
+    /// Encodes the capture as PNG, or EXR for HDR formats.
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut bytes = Cursor::new(Vec::new());
+        if self.is_hdr() {
+            image::DynamicImage::ImageRgba32F(self.to_image()?.to_rgba32f())
+                .write_to(&mut bytes, image::ImageOutputFormat::OpenExr)?;
+        } else {
+            image::DynamicImage::ImageRgba8(self.to_image()?.to_rgba8())
+                .write_to(&mut bytes, image::ImageOutputFormat::Png)?;
+        }
+        Ok(bytes.into_inner())
+    }
+
+    /// Writes the capture as PNG, or EXR for HDR formats, regardless of the
+    /// extension in `path`.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        std::fs::write(path, self.encode()?)
+            .with_context(|| format!("capture: failed to write {}", path.display()))
+    }
+}
+
+fn f16_to_f32(half: u16) -> f32 {
+    let sign = ((half >> 15) as u32) << 31;
+    let exponent = ((half >> 10) & 0x1f) as u32;
+    let mantissa = (half & 0x3ff) as u32;
+
+    let bits = match exponent {
+        // zero & subnormals
+        0 => {
+            if mantissa == 0 {
+                sign
+            } else {
+                let value = mantissa as f32 / (1 << 24) as f32;
+                return if sign != 0 { -value } else { value };
+            }
+        }
+        // inf & nan
+        0x1f => sign | 0x7f80_0000 | (mantissa << 13),
+        _ => sign | ((exponent + 127 - 15) << 23) | (mantissa << 13),
+    };
+
+    f32::from_bits(bits)
+}
@@ -1,4 +1,5 @@
 use super::model::Vertex;
+use crate::shaders::structs::{wgsl_struct, Usage};
 
 pub struct Instance {
     pub position: cgmath::Vector3<f32>,
@@ -9,18 +10,25 @@ pub struct Instance {
 #[repr(C)]
 #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct InstanceRaw {
-    pub model: [[f32; 4]; 4],
-    pub normal: [[f32; 3]; 3],
+    pub model_matrix: [[f32; 4]; 4],
+    pub normal_matrix: [[f32; 3]; 3],
 }
 
+// location 4 is free, it was the bitangent of the vertex
+wgsl_struct!(
+    InstanceRaw as InstanceInput,
+    Usage::Vertex { first_location: 5 },
+    [model_matrix, normal_matrix]
+);
+
 impl Instance {
     pub fn to_raw(&self) -> InstanceRaw {
         InstanceRaw {
-            model: (cgmath::Matrix4::from_translation(self.position)
+            model_matrix: (cgmath::Matrix4::from_translation(self.position)
                 * cgmath::Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
                 * cgmath::Matrix4::from(self.rotation))
             .into(),
-            normal: cgmath::Matrix3::from(self.rotation).into(),
+            normal_matrix: cgmath::Matrix3::from(self.rotation).into(),
         }
     }
 }
@@ -0,0 +1,635 @@
+use std::io::Read;
+
+use ktx2::{Format, SupercompressionScheme};
+
+use crate::core::resources::LoadError;
+
+/// A KTX2 texture ready for upload, all mip levels in one buffer.
+pub struct Ktx2Image {
+    pub width: u32,
+    pub height: u32,
+    pub format: wgpu::TextureFormat,
+    pub mip_level_count: u32,
+    pub data: Vec<u8>,
+}
+
+/// Parses a KTX2 file, for a format the device can sample from.
+///
+/// Only 2D textures are supported, without supercompression or with
+/// Zstandard, and BasisLZ for ETC1S. Basis Universal payloads (ETC1S or
+/// UASTC) are transcoded to BC7, ETC2 or ASTC, whichever the device supports,
+/// and decompressed to RGBA8 when it has none of them. The transcoder isn't
+/// built for wasm, Basis Universal fails with `LoadError::UnsupportedImage`
+/// there.
+///
+/// `srgb` overrides the color space of the file, like the glTF texture slots
+/// do for other images.
+pub fn decode_ktx2(bytes: &[u8], features: wgpu::Features, srgb: bool) -> Result<Ktx2Image, LoadError> {
+    let reader = ktx2::Reader::new(bytes).map_err(|e| LoadError::UnsupportedImage(format!("KTX2 {:?}", e)))?;
+    let header = reader.header();
+
+    if header.pixel_depth > 1 || header.layer_count > 1 || header.face_count != 1 {
+        return Err(LoadError::UnsupportedImage(
+            "KTX2 texture arrays, cubemaps & 3D textures".to_string(),
+        ));
+    }
+
+    let image = match header.format {
+        Some(format) => decode_levels(&reader, format, features, srgb)?,
+        None => transcode_basis(&reader, features, srgb)?,
+    };
+    if image.data.len() < image.expected_size() {
+        return Err(LoadError::UnsupportedImage(format!(
+            "KTX2 data is {} bytes, expected {}",
+            image.data.len(),
+            image.expected_size()
+        )));
+    }
+    Ok(image)
+}
+
+/// The levels of a KTX2 file with a GPU format, uploaded as they are.
+fn decode_levels(
+    reader: &ktx2::Reader<&[u8]>,
+    format: Format,
+    features: wgpu::Features,
+    srgb: bool,
+) -> Result<Ktx2Image, LoadError> {
+    let header = reader.header();
+    let format = ktx2_format_to_wgpu(format)
+        .ok_or_else(|| LoadError::UnsupportedImage(format!("KTX2 format {:?}", format)))?;
+    let format = if srgb { format.add_srgb_suffix() } else { format.remove_srgb_suffix() };
+    if !features.contains(format.required_features()) {
+        return Err(LoadError::UnsupportedImage(format!("KTX2 format {:?} on this adapter", format)));
+    }
+
+    let width = header.pixel_width;
+    let height = header.pixel_height.max(1);
+    let (block_width, block_height) = format.block_dimensions();
+    if width % block_width != 0 || height % block_height != 0 {
+        return Err(LoadError::UnsupportedImage(format!(
+            "KTX2 {:?} of {}x{}, not whole {}x{} blocks",
+            format, width, height, block_width, block_height
+        )));
+    }
+    // 0 means the loader should generate them, only the first level is
+    // there. `Texture::from_ktx2` does that for RGBA8, compressed formats
+    // keep the one level.
+    let mip_level_count = header.level_count.max(1);
+    let max_mips = wgpu::Extent3d {
+        width,
+        height,
+        depth_or_array_layers: 1,
+    }
+    .max_mips(wgpu::TextureDimension::D2);
+    if mip_level_count > max_mips {
+        return Err(LoadError::UnsupportedImage(format!(
+            "KTX2 with {} levels, a {}x{} mip chain has {}",
+            mip_level_count, width, height, max_mips
+        )));
+    }
+
+    let mut data = Vec::new();
+    for level in reader.levels() {
+        data.extend(level_data(header.supercompression_scheme, level)?);
+    }
+
+    Ok(Ktx2Image {
+        width,
+        height,
+        format,
+        mip_level_count,
+        data,
+    })
+}
+
+/// A level without its supercompression.
+fn level_data(scheme: Option<SupercompressionScheme>, level: &[u8]) -> Result<Vec<u8>, LoadError> {
+    match scheme {
+        None => Ok(level.to_vec()),
+        Some(SupercompressionScheme::Zstandard) => {
+            let mut data = Vec::new();
+            let mut decoder = ruzstd::StreamingDecoder::new(level)
+                .map_err(|e| LoadError::UnsupportedImage(format!("KTX2 Zstandard {}", e)))?;
+            decoder
+                .read_to_end(&mut data)
+                .map_err(|e| LoadError::UnsupportedImage(format!("KTX2 Zstandard {}", e)))?;
+            Ok(data)
+        }
+        Some(scheme) => Err(LoadError::UnsupportedImage(format!("KTX2 supercompression {:?}", scheme))),
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn transcode_basis(_reader: &ktx2::Reader<&[u8]>, _features: wgpu::Features, _srgb: bool) -> Result<Ktx2Image, LoadError> {
+    Err(LoadError::UnsupportedImage("KTX2 Basis Universal transcoding on wasm".to_string()))
+}
+
+/// Transcodes a Basis Universal texture to the first of BC7, ETC2 & ASTC the
+/// device supports, or to RGBA8.
+///
+/// The transcoder only reads `.basis` files, the KTX2 codebooks & slices are
+/// repacked into one first.
+#[cfg(not(target_arch = "wasm32"))]
+fn transcode_basis(reader: &ktx2::Reader<&[u8]>, features: wgpu::Features, srgb: bool) -> Result<Ktx2Image, LoadError> {
+    use basis_universal::{TranscodeParameters, Transcoder, TranscoderTextureFormat};
+    use ktx2::{BasicDataFormatDescriptor, ColorModel, DataFormatDescriptorHeader};
+
+    let header = reader.header();
+    let width = header.pixel_width;
+    let height = header.pixel_height.max(1);
+    let level_count = header.level_count.max(1);
+
+    let dfd = reader
+        .data_format_descriptors()
+        .find(|dfd| dfd.header == DataFormatDescriptorHeader::BASIC)
+        .and_then(|dfd| BasicDataFormatDescriptor::parse(dfd.data).ok())
+        .ok_or_else(|| LoadError::UnsupportedImage("KTX2 without a data format descriptor".to_string()))?;
+    let basis = match dfd.color_model {
+        Some(ColorModel::ETC1S) => {
+            // the second sample is the alpha slice
+            let has_alpha = dfd.sample_information().count() > 1;
+            BasisFile::from_etc1s(reader, has_alpha)?
+        }
+        Some(ColorModel::UASTC) => {
+            let channel = dfd.sample_information().next().map(|sample| sample.channel_type);
+            // RGBA or RRRG
+            let has_alpha = matches!(channel, Some(3 | 5));
+            BasisFile::from_uastc(reader, has_alpha)?
+        }
+        model => return Err(LoadError::UnsupportedImage(format!("KTX2 color model {:?}", model))),
+    }
+    .to_bytes();
+
+    // compressed textures need whole blocks, at least for the first level
+    let whole_blocks = width % 4 == 0 && height % 4 == 0;
+    let targets = [
+        (wgpu::Features::TEXTURE_COMPRESSION_BC, TranscoderTextureFormat::BC7_RGBA, wgpu::TextureFormat::Bc7RgbaUnorm),
+        (wgpu::Features::TEXTURE_COMPRESSION_ETC2, TranscoderTextureFormat::ETC2_RGBA, wgpu::TextureFormat::Etc2Rgba8Unorm),
+        (
+            wgpu::Features::TEXTURE_COMPRESSION_ASTC,
+            TranscoderTextureFormat::ASTC_4x4_RGBA,
+            wgpu::TextureFormat::Astc {
+                block: wgpu::AstcBlock::B4x4,
+                channel: wgpu::AstcChannel::Unorm,
+            },
+        ),
+    ];
+    let (target, format) = targets
+        .into_iter()
+        .find(|(feature, _, _)| whole_blocks && features.contains(*feature))
+        .map(|(_, target, format)| (target, format))
+        .unwrap_or((TranscoderTextureFormat::RGBA32, wgpu::TextureFormat::Rgba8Unorm));
+    let format = if srgb { format.add_srgb_suffix() } else { format };
+
+    let transcode_error = |e: String| LoadError::UnsupportedImage(format!("KTX2 Basis Universal {}", e));
+    let mut transcoder = Transcoder::new();
+    transcoder
+        .prepare_transcoding(&basis)
+        .map_err(|_| transcode_error("codebooks can't be decoded".to_string()))?;
+    let mut data = Vec::new();
+    for level in 0..level_count {
+        let parameters = TranscodeParameters {
+            image_index: 0,
+            level_index: level,
+            ..Default::default()
+        };
+        let level_data = transcoder
+            .transcode_image_level(&basis, target, parameters)
+            .map_err(|e| transcode_error(format!("level {} {:?}", level, e)))?;
+        data.extend(level_data);
+    }
+    transcoder.end_transcoding();
+
+    Ok(Ktx2Image {
+        width,
+        height,
+        format,
+        mip_level_count: level_count,
+        data,
+    })
+}
+
+/// A `.basis` file being put together from a KTX2 file, with one image.
+#[cfg(not(target_arch = "wasm32"))]
+struct BasisFile<'a> {
+    etc1s: bool,
+    has_alpha: bool,
+    /// ETC1S codebooks: endpoint count & data, selector count & data.
+    endpoints: (u32, &'a [u8]),
+    selectors: (u32, &'a [u8]),
+    tables: &'a [u8],
+    slices: Vec<BasisSlice>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+struct BasisSlice {
+    level: u32,
+    alpha: bool,
+    width: u32,
+    height: u32,
+    data: Vec<u8>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<'a> BasisFile<'a> {
+    /// ETC1S, BasisLZ supercompressed: the codebooks are in the global data,
+    /// followed by where the slices of each level are.
+    fn from_etc1s(reader: &'a ktx2::Reader<&[u8]>, has_alpha: bool) -> Result<Self, LoadError> {
+        let header = reader.header();
+        let invalid = || LoadError::UnsupportedImage("KTX2 ETC1S global data is invalid".to_string());
+        if header.supercompression_scheme != Some(SupercompressionScheme::BasisLZ) {
+            return Err(LoadError::UnsupportedImage(format!(
+                "KTX2 ETC1S with supercompression {:?}",
+                header.supercompression_scheme
+            )));
+        }
+
+        let global = reader.supercompression_global_data();
+        let u16_at = |offset: usize| global.get(offset..offset + 2).map(|b| u16::from_le_bytes([b[0], b[1]]) as u32);
+        let u32_at = |offset: usize| {
+            global
+                .get(offset..offset + 4)
+                .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        };
+        let endpoint_count = u16_at(0).ok_or_else(invalid)?;
+        let selector_count = u16_at(2).ok_or_else(invalid)?;
+        let endpoints_length = u32_at(4).ok_or_else(invalid)? as usize;
+        let selectors_length = u32_at(8).ok_or_else(invalid)? as usize;
+        let tables_length = u32_at(12).ok_or_else(invalid)? as usize;
+
+        // the header, then the image description of each level
+        const GLOBAL_HEADER_SIZE: usize = 20;
+        const IMAGE_DESC_SIZE: usize = 20;
+        let endpoints_start = GLOBAL_HEADER_SIZE + IMAGE_DESC_SIZE * reader.levels().len();
+        let selectors_start = endpoints_start + endpoints_length;
+        let tables_start = selectors_start + selectors_length;
+        let endpoints = global.get(endpoints_start..selectors_start).ok_or_else(invalid)?;
+        let selectors = global.get(selectors_start..tables_start).ok_or_else(invalid)?;
+        let tables = global.get(tables_start..tables_start + tables_length).ok_or_else(invalid)?;
+
+        let mut slices = Vec::new();
+        for (level, level_bytes) in reader.levels().enumerate() {
+            let desc = GLOBAL_HEADER_SIZE + IMAGE_DESC_SIZE * level;
+            let (width, height) = level_size(header.pixel_width, header.pixel_height, level as u32);
+            let mut slice = |offset: usize, length: usize, alpha: bool| -> Result<(), LoadError> {
+                let offset = u32_at(desc + offset).ok_or_else(invalid)? as usize;
+                let length = u32_at(desc + length).ok_or_else(invalid)? as usize;
+                let data = level_bytes.get(offset..offset + length).ok_or_else(invalid)?;
+                slices.push(BasisSlice {
+                    level: level as u32,
+                    alpha,
+                    width,
+                    height,
+                    data: data.to_vec(),
+                });
+                Ok(())
+            };
+            slice(4, 8, false)?;
+            if has_alpha {
+                slice(12, 16, true)?;
+            }
+        }
+
+        Ok(Self {
+            etc1s: true,
+            has_alpha,
+            endpoints: (endpoint_count, endpoints),
+            selectors: (selector_count, selectors),
+            tables,
+            slices,
+        })
+    }
+
+    /// UASTC: the blocks of each level as they are, maybe Zstandard
+    /// compressed.
+    fn from_uastc(reader: &ktx2::Reader<&[u8]>, has_alpha: bool) -> Result<Self, LoadError> {
+        let header = reader.header();
+        let mut slices = Vec::new();
+        for (level, level_bytes) in reader.levels().enumerate() {
+            let (width, height) = level_size(header.pixel_width, header.pixel_height, level as u32);
+            slices.push(BasisSlice {
+                level: level as u32,
+                alpha: false,
+                width,
+                height,
+                data: level_data(header.supercompression_scheme, level_bytes)?,
+            });
+        }
+
+        Ok(Self {
+            etc1s: false,
+            has_alpha,
+            endpoints: (0, &[]),
+            selectors: (0, &[]),
+            tables: &[],
+            slices,
+        })
+    }
+
+    /// The file, laid out as header, slice descriptions, codebooks, tables and
+    /// slice data. All the fields are little endian.
+    fn to_bytes(&self) -> Vec<u8> {
+        const HEADER_SIZE: usize = 77;
+        const SLICE_DESC_SIZE: usize = 23;
+        const SIGNATURE: u64 = ((b'B' as u64) << 8) | b's' as u64;
+        const VERSION: u64 = 0x13;
+        const FLAG_ETC1S: u64 = 1;
+        const FLAG_HAS_ALPHA_SLICES: u64 = 4;
+
+        let endpoints_offset = HEADER_SIZE + SLICE_DESC_SIZE * self.slices.len();
+        let selectors_offset = endpoints_offset + self.endpoints.1.len();
+        let tables_offset = selectors_offset + self.selectors.1.len();
+        let mut slice_offset = tables_offset + self.tables.len();
+
+        let mut flags = 0;
+        if self.etc1s {
+            flags |= FLAG_ETC1S;
+        }
+        if self.has_alpha {
+            flags |= FLAG_HAS_ALPHA_SLICES;
+        }
+
+        let mut file = Vec::new();
+        let mut put = |value: u64, size: usize| file.extend_from_slice(&value.to_le_bytes()[..size]);
+        put(SIGNATURE, 2);
+        put(VERSION, 2);
+        put(HEADER_SIZE as u64, 2);
+        // header & data CRC16, filled in last
+        put(0, 2);
+        put(0, 4);
+        put(0, 2);
+        put(self.slices.len() as u64, 3);
+        // total images
+        put(1, 3);
+        put(!self.etc1s as u64, 1);
+        put(flags, 2);
+        // 2D, not a video
+        put(0, 1);
+        put(0, 3);
+        // reserved & user data
+        put(0, 4);
+        put(0, 4);
+        put(0, 4);
+        put(self.endpoints.0 as u64, 2);
+        put(endpoints_offset as u64, 4);
+        put(self.endpoints.1.len() as u64, 3);
+        put(self.selectors.0 as u64, 2);
+        put(selectors_offset as u64, 4);
+        put(self.selectors.1.len() as u64, 3);
+        put(tables_offset as u64, 4);
+        put(self.tables.len() as u64, 4);
+        put(HEADER_SIZE as u64, 4);
+        // no extended data
+        put(0, 4);
+        put(0, 4);
+
+        for slice in &self.slices {
+            put(0, 3);
+            put(slice.level as u64, 1);
+            put(slice.alpha as u64, 1);
+            put(slice.width as u64, 2);
+            put(slice.height as u64, 2);
+            put(slice.width.div_ceil(4) as u64, 2);
+            put(slice.height.div_ceil(4) as u64, 2);
+            put(slice_offset as u64, 4);
+            put(slice.data.len() as u64, 4);
+            put(crc16(&slice.data) as u64, 2);
+            slice_offset += slice.data.len();
+        }
+
+        file.extend_from_slice(self.endpoints.1);
+        file.extend_from_slice(self.selectors.1);
+        file.extend_from_slice(self.tables);
+        for slice in &self.slices {
+            file.extend_from_slice(&slice.data);
+        }
+
+        let data_size = (file.len() - HEADER_SIZE) as u32;
+        file[8..12].copy_from_slice(&data_size.to_le_bytes());
+        let data_crc = crc16(&file[HEADER_SIZE..]);
+        file[12..14].copy_from_slice(&data_crc.to_le_bytes());
+        // covers the header from the data size on
+        let header_crc = crc16(&file[8..HEADER_SIZE]);
+        file[6..8].copy_from_slice(&header_crc.to_le_bytes());
+        file
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn level_size(width: u32, height: u32, level: u32) -> (u32, u32) {
+    ((width >> level).max(1), (height >> level).max(1))
+}
+
+/// The CRC16 of the `.basis` format.
+#[cfg(not(target_arch = "wasm32"))]
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = !0u16;
+    for &byte in data {
+        let q = byte as u16 ^ (crc >> 8);
+        let k = (q >> 4) ^ q;
+        crc = (crc << 8) ^ k ^ (k << 5) ^ (k << 12);
+    }
+    !crc
+}
+
+impl Ktx2Image {
+    pub fn size(&self) -> wgpu::Extent3d {
+        wgpu::Extent3d {
+            width: self.width,
+            height: self.height,
+            depth_or_array_layers: 1,
+        }
+    }
+
+    /// Bytes needed for all the mip levels, compressed levels are rounded up
+    /// to whole blocks.
+    fn expected_size(&self) -> usize {
+        let (block_width, block_height) = self.format.block_dimensions();
+        let block_size = self.format.block_size(None).unwrap_or(4);
+        (0..self.mip_level_count)
+            .map(|level| {
+                let size = self
+                    .size()
+                    .mip_level_size(level, wgpu::TextureDimension::D2)
+                    .physical_size(self.format);
+                (size.width / block_width * size.height / block_height * block_size) as usize
+            })
+            .sum()
+    }
+}
+
+fn ktx2_format_to_wgpu(format: Format) -> Option<wgpu::TextureFormat> {
+    use wgpu::{AstcBlock, AstcChannel, TextureFormat};
+
+    let format = match format {
+        Format::R8_UNORM => TextureFormat::R8Unorm,
+        Format::R8G8_UNORM => TextureFormat::Rg8Unorm,
+        Format::R8G8B8A8_UNORM => TextureFormat::Rgba8Unorm,
+        Format::R8G8B8A8_SRGB => TextureFormat::Rgba8UnormSrgb,
+        Format::R16G16B16A16_SFLOAT => TextureFormat::Rgba16Float,
+        Format::R32G32B32A32_SFLOAT => TextureFormat::Rgba32Float,
+        Format::BC1_RGB_UNORM_BLOCK | Format::BC1_RGBA_UNORM_BLOCK => TextureFormat::Bc1RgbaUnorm,
+        Format::BC1_RGB_SRGB_BLOCK | Format::BC1_RGBA_SRGB_BLOCK => TextureFormat::Bc1RgbaUnormSrgb,
+        Format::BC2_UNORM_BLOCK => TextureFormat::Bc2RgbaUnorm,
+        Format::BC2_SRGB_BLOCK => TextureFormat::Bc2RgbaUnormSrgb,
+        Format::BC3_UNORM_BLOCK => TextureFormat::Bc3RgbaUnorm,
+        Format::BC3_SRGB_BLOCK => TextureFormat::Bc3RgbaUnormSrgb,
+        Format::BC4_UNORM_BLOCK => TextureFormat::Bc4RUnorm,
+        Format::BC4_SNORM_BLOCK => TextureFormat::Bc4RSnorm,
+        Format::BC5_UNORM_BLOCK => TextureFormat::Bc5RgUnorm,
+        Format::BC5_SNORM_BLOCK => TextureFormat::Bc5RgSnorm,
+        Format::BC6H_UFLOAT_BLOCK => TextureFormat::Bc6hRgbUfloat,
+        Format::BC6H_SFLOAT_BLOCK => TextureFormat::Bc6hRgbFloat,
+        Format::BC7_UNORM_BLOCK => TextureFormat::Bc7RgbaUnorm,
+        Format::BC7_SRGB_BLOCK => TextureFormat::Bc7RgbaUnormSrgb,
+        Format::ETC2_R8G8B8_UNORM_BLOCK => TextureFormat::Etc2Rgb8Unorm,
+        Format::ETC2_R8G8B8_SRGB_BLOCK => TextureFormat::Etc2Rgb8UnormSrgb,
+        Format::ETC2_R8G8B8A1_UNORM_BLOCK => TextureFormat::Etc2Rgb8A1Unorm,
+        Format::ETC2_R8G8B8A1_SRGB_BLOCK => TextureFormat::Etc2Rgb8A1UnormSrgb,
+        Format::ETC2_R8G8B8A8_UNORM_BLOCK => TextureFormat::Etc2Rgba8Unorm,
+        Format::ETC2_R8G8B8A8_SRGB_BLOCK => TextureFormat::Etc2Rgba8UnormSrgb,
+        Format::EAC_R11_UNORM_BLOCK => TextureFormat::EacR11Unorm,
+        Format::EAC_R11_SNORM_BLOCK => TextureFormat::EacR11Snorm,
+        Format::EAC_R11G11_UNORM_BLOCK => TextureFormat::EacRg11Unorm,
+        Format::EAC_R11G11_SNORM_BLOCK => TextureFormat::EacRg11Snorm,
+        Format::ASTC_4x4_UNORM_BLOCK => TextureFormat::Astc {
+            block: AstcBlock::B4x4,
+            channel: AstcChannel::Unorm,
+        },
+        Format::ASTC_4x4_SRGB_BLOCK => TextureFormat::Astc {
+            block: AstcBlock::B4x4,
+            channel: AstcChannel::UnormSrgb,
+        },
+        _ => return None,
+    };
+    Some(format)
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    // 16x16 with mips, 8x8 quadrants: red, green at half alpha, blue, and
+    // transparent white
+    const ETC1S: &[u8] = include_bytes!("../../tests/assets/textures/etc1s.ktx2");
+    const UASTC: &[u8] = include_bytes!("../../tests/assets/textures/uastc.ktx2");
+
+    fn assert_quadrants(image: &Ktx2Image) {
+        let expected = [
+            ((4, 4), [255, 0, 0, 255]),
+            ((12, 4), [0, 255, 0, 128]),
+            ((4, 12), [0, 0, 255, 255]),
+            ((12, 12), [255, 255, 255, 0]),
+        ];
+        for ((x, y), color) in expected {
+            let offset = (y * image.width as usize + x) * 4;
+            let pixel = &image.data[offset..offset + 4];
+            for (channel, expected) in pixel.iter().zip(color) {
+                // alpha is exact, the colors of transparent texels don't matter
+                if color[3] != 0 && channel.abs_diff(expected) > 16 {
+                    panic!("pixel ({}, {}) is {:?}, expected {:?}", x, y, pixel, color);
+                }
+            }
+            assert_eq!(pixel[3], color[3], "alpha of pixel ({}, {})", x, y);
+        }
+    }
+
+    /// A KTX2 file without supercompression or data format descriptor.
+    fn ktx2_file(format: Format, width: u32, height: u32, levels: &[Vec<u8>]) -> Vec<u8> {
+        const HEADER_SIZE: usize = 80;
+        const LEVEL_INDEX_SIZE: usize = 24;
+
+        let mut file = b"\xABKTX 20\xBB\r\n\x1A\n".to_vec();
+        // format, type size, width, height, depth, layers, faces, levels &
+        // supercompression
+        for value in [format.0.get(), 1, width, height, 0, 0, 1, levels.len() as u32, 0] {
+            file.extend_from_slice(&value.to_le_bytes());
+        }
+        // no descriptor, key/values or global data
+        file.resize(HEADER_SIZE, 0);
+
+        let mut offset = HEADER_SIZE + LEVEL_INDEX_SIZE * levels.len();
+        for level in levels {
+            for value in [offset, level.len(), level.len()] {
+                file.extend_from_slice(&(value as u64).to_le_bytes());
+            }
+            offset += level.len();
+        }
+        for level in levels {
+            file.extend_from_slice(level);
+        }
+        file
+    }
+
+    #[test]
+    fn compressed_levels_need_whole_blocks() {
+        let bc = wgpu::Features::TEXTURE_COMPRESSION_BC;
+        // 8x8 BC1: 4 blocks of 8 bytes, then one block for each level
+        let levels = [vec![0; 32], vec![0; 8], vec![0; 8], vec![0; 8]];
+        let image = decode_ktx2(&ktx2_file(Format::BC1_RGBA_UNORM_BLOCK, 8, 8, &levels), bc, true).unwrap();
+        assert_eq!(image.format, wgpu::TextureFormat::Bc1RgbaUnormSrgb);
+        assert_eq!(image.mip_level_count, 4);
+
+        let file = ktx2_file(Format::BC1_RGBA_UNORM_BLOCK, 6, 6, &[vec![0; 32]]);
+        assert!(matches!(decode_ktx2(&file, bc, true), Err(LoadError::UnsupportedImage(_))));
+    }
+
+    #[test]
+    fn levels_beyond_mip_chain() {
+        // 8x8 RGBA8 has 4 levels
+        let mut levels = vec![vec![0; 256], vec![0; 64], vec![0; 16], vec![0; 4]];
+        let image = decode_ktx2(&ktx2_file(Format::R8G8B8A8_UNORM, 8, 8, &levels), wgpu::Features::empty(), true).unwrap();
+        assert_eq!(image.mip_level_count, 4);
+
+        levels.push(vec![0; 4]);
+        let file = ktx2_file(Format::R8G8B8A8_UNORM, 8, 8, &levels);
+        assert!(matches!(
+            decode_ktx2(&file, wgpu::Features::empty(), true),
+            Err(LoadError::UnsupportedImage(_))
+        ));
+    }
+
+    #[test]
+    fn basis_decompresses_without_compressed_formats() {
+        for (name, bytes) in [("ETC1S", ETC1S), ("UASTC", UASTC)] {
+            let image = decode_ktx2(bytes, wgpu::Features::empty(), true).unwrap_or_else(|e| panic!("{}: {}", name, e));
+            assert_eq!(image.format, wgpu::TextureFormat::Rgba8UnormSrgb, "{}", name);
+            assert_eq!((image.width, image.height, image.mip_level_count), (16, 16, 5), "{}", name);
+            assert_eq!(image.data.len(), image.expected_size(), "{}", name);
+            assert_quadrants(&image);
+        }
+    }
+
+    #[test]
+    fn basis_transcodes_to_supported_format() {
+        let astc = wgpu::TextureFormat::Astc {
+            block: wgpu::AstcBlock::B4x4,
+            channel: wgpu::AstcChannel::UnormSrgb,
+        };
+        let targets = [
+            (wgpu::Features::TEXTURE_COMPRESSION_BC, wgpu::TextureFormat::Bc7RgbaUnormSrgb),
+            (wgpu::Features::TEXTURE_COMPRESSION_ETC2, wgpu::TextureFormat::Etc2Rgba8UnormSrgb),
+            (wgpu::Features::TEXTURE_COMPRESSION_ASTC, astc),
+            // BC7 first
+            (
+                wgpu::Features::TEXTURE_COMPRESSION_BC | wgpu::Features::TEXTURE_COMPRESSION_ETC2,
+                wgpu::TextureFormat::Bc7RgbaUnormSrgb,
+            ),
+        ];
+        for (name, bytes) in [("ETC1S", ETC1S), ("UASTC", UASTC)] {
+            for (features, format) in targets {
+                let image = decode_ktx2(bytes, features, true).unwrap_or_else(|e| panic!("{}: {}", name, e));
+                assert_eq!(image.format, format, "{} with {:?}", name, features);
+                // 16 + 4 + 1 + 1 + 1 blocks
+                assert_eq!(image.data.len(), 23 * 16, "{} with {:?}", name, features);
+            }
+        }
+    }
+
+    #[test]
+    fn basis_without_srgb_is_linear() {
+        let image = decode_ktx2(UASTC, wgpu::Features::TEXTURE_COMPRESSION_BC, false).unwrap();
+        assert_eq!(image.format, wgpu::TextureFormat::Bc7RgbaUnorm);
+    }
+}
@@ -0,0 +1,171 @@
+use std::mem;
+
+use super::camera::CameraUniform;
+use super::light::LightUniform;
+use super::material::MaterialUniform;
+use super::state::GlobalUniforms;
+
+// The bind group layouts of the passes, as entries so the tests can check the
+// shaders against them without a device.
+
+/// A bind group layout, passes list the ones they use by group index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Group {
+    Global,
+    LightDepth,
+    GeometryDepth,
+    Texture,
+    Mipmap,
+}
+
+impl Group {
+    pub fn entries(self) -> Vec<wgpu::BindGroupLayoutEntry> {
+        match self {
+            Group::Global => global_entries().to_vec(),
+            Group::LightDepth => light_depth_entries().to_vec(),
+            Group::GeometryDepth => geometry_depth_entries().to_vec(),
+            Group::Texture => texture_entries().to_vec(),
+            Group::Mipmap => mipmap_entries().to_vec(),
+        }
+    }
+}
+
+/// The geometry passes, `pbr.wgsl`.
+pub fn pbr_groups() -> [Group; 3] {
+    [Group::Global, Group::LightDepth, Group::Texture]
+}
+
+/// The light depth passes, `depth.wgsl`.
+pub fn depth_groups() -> [Group; 2] {
+    [Group::Global, Group::Texture]
+}
+
+/// The light debug pass, `light_debug.wgsl`.
+pub fn light_debug_groups() -> [Group; 1] {
+    [Group::Global]
+}
+
+/// The fog pass, `fog.wgsl`.
+pub fn fog_groups() -> [Group; 3] {
+    [Group::Global, Group::LightDepth, Group::GeometryDepth]
+}
+
+/// The mipmap passes, `mipmap.wgsl`.
+pub fn mipmap_groups() -> [Group; 1] {
+    [Group::Mipmap]
+}
+
+/// Group 0 of every pass: camera, light & global uniforms.
+pub fn global_entries() -> [wgpu::BindGroupLayoutEntry; 3] {
+    [
+        // CameraUniform
+        uniform_entry(0, wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT, mem::size_of::<CameraUniform>()),
+        // LightUniform
+        uniform_entry(1, wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT, mem::size_of::<LightUniform>()),
+        // global_uniforms
+        uniform_entry(2, wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT, mem::size_of::<GlobalUniforms>()),
+    ]
+}
+
+/// The shadow maps of the lit passes.
+pub fn light_depth_entries() -> [wgpu::BindGroupLayoutEntry; 2] {
+    [
+        // light cubemap
+        wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2Array,
+                sample_type: wgpu::TextureSampleType::Depth,
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 1,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+            count: None,
+        },
+    ]
+}
+
+/// The depth of the geometry pass, for the fog pass.
+pub fn geometry_depth_entries() -> [wgpu::BindGroupLayoutEntry; 1] {
+    [
+        // geometry depth, read with textureLoad
+        wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            },
+            count: None,
+        },
+    ]
+}
+
+/// The textures & uniform of a material.
+pub fn texture_entries() -> [wgpu::BindGroupLayoutEntry; 11] {
+    [
+        // diffuse
+        texture_entry(0),
+        sampler_entry(1),
+        // normal
+        texture_entry(2),
+        sampler_entry(3),
+        // metallic + roughness
+        texture_entry(4),
+        sampler_entry(5),
+        // material uniform
+        uniform_entry(6, wgpu::ShaderStages::FRAGMENT, mem::size_of::<MaterialUniform>()),
+        // emissive
+        texture_entry(7),
+        sampler_entry(8),
+        // occlusion
+        texture_entry(9),
+        sampler_entry(10),
+    ]
+}
+
+/// The level a mip is rendered from.
+pub fn mipmap_entries() -> [wgpu::BindGroupLayoutEntry; 2] {
+    [texture_entry(0), sampler_entry(1)]
+}
+
+fn uniform_entry(binding: u32, visibility: wgpu::ShaderStages, size: usize) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: wgpu::BufferSize::new(size as u64),
+        },
+        count: None,
+    }
+}
+
+fn texture_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+        },
+        count: None,
+    }
+}
+
+fn sampler_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+        count: None,
+    }
+}
@@ -8,6 +8,8 @@ use super::{
 
 use cgmath::{Matrix4, Vector3};
 
+use crate::shaders::structs::{wgsl_struct, Usage};
+
 #[repr(C)]
 #[derive(Debug, Default, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct LightUniform {
@@ -17,6 +19,8 @@ pub struct LightUniform {
     pub matrices: [[[f32; 4]; 4]; 6],
 }
 
+wgsl_struct!(LightUniform, Usage::Uniform, [position, _padding, color, matrices]);
+
 impl LightUniform {
     pub fn new(position: [f32; 3], color: [f32; 4]) -> Self {
         let mut s = Self {
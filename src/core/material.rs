@@ -1,35 +1,109 @@
+use std::sync::Arc;
+
 use wgpu::util::DeviceExt;
-use crate::core::texture::Texture;
+use crate::core::texture::{SamplerCache, SamplerKey, Texture};
+use crate::shaders::structs::{wgsl_struct, Usage};
+
+/// How the alpha of the base color is used, see glTF `alphaMode`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AlphaMode {
+    Opaque,
+    /// Discarded below the cutoff.
+    Mask,
+    /// Blended, drawn back to front after everything else.
+    Blend,
+}
+
+/// A texture shared between materials, with the sampler of one material slot.
+#[derive(Debug, Clone)]
+pub struct MaterialTexture {
+    pub texture: Arc<Texture>,
+    pub sampler: Arc<wgpu::Sampler>,
+    pub sampler_key: SamplerKey,
+}
+
+impl MaterialTexture {
+    pub fn new(device: &wgpu::Device, texture: Arc<Texture>, samplers: &mut SamplerCache, sampler_key: SamplerKey) -> Self {
+        Self {
+            texture,
+            sampler: samplers.get(device, sampler_key),
+            sampler_key,
+        }
+    }
+}
 
 pub struct Material {
     pub name: String,
-    pub diffuse_texture: Texture,
-    pub normal_texture: Texture,
-    pub metallic_roughness_texture: Texture,
+    pub diffuse_texture: MaterialTexture,
+    pub normal_texture: MaterialTexture,
+    pub metallic_roughness_texture: MaterialTexture,
+    pub emissive_texture: MaterialTexture,
+    pub occlusion_texture: MaterialTexture,
+    pub alpha_mode: AlphaMode,
+    /// Back faces are drawn too, with flipped normals.
+    pub double_sided: bool,
     pub material_uniform: MaterialUniform,
+    pub material_uniform_buffer: wgpu::Buffer,
     pub bind_group: wgpu::BindGroup,
 }
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct MaterialUniform {
-    // metallic, roughness, none, none
-    pub factors: [f32; 4],
+    pub base_color_factor: [f32; 4],
+    pub emissive_factor: [f32; 3],
+    pub occlusion_strength: f32,
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    pub normal_scale: f32,
+    pub alpha_cutoff: f32,
 }
 
+wgsl_struct!(
+    MaterialUniform,
+    Usage::Uniform,
+    [
+        base_color_factor,
+        emissive_factor,
+        occlusion_strength,
+        metallic_factor,
+        roughness_factor,
+        normal_scale,
+        alpha_cutoff,
+    ]
+);
+
 impl Material {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         device: &wgpu::Device,
         name: &str,
-        diffuse_texture: Texture,
-        normal_texture: Texture,
-        metallic_roughness_texture: Texture,
+        diffuse_texture: MaterialTexture,
+        normal_texture: MaterialTexture,
+        metallic_roughness_texture: MaterialTexture,
+        emissive_texture: MaterialTexture,
+        occlusion_texture: MaterialTexture,
+        base_color_factor: [f32; 4],
+        emissive_factor: [f32; 3],
         metallic_factor: f32,
         roughness_factor: f32,
+        normal_scale: f32,
+        occlusion_strength: f32,
+        alpha_mode: AlphaMode,
+        alpha_cutoff: f32,
+        double_sided: bool,
         layout: &wgpu::BindGroupLayout,
     ) -> Self {
+        // only masked materials discard
+        let alpha_cutoff = if alpha_mode == AlphaMode::Mask { alpha_cutoff } else { 0.0 };
         let material_uniform = MaterialUniform {
-            factors: [metallic_factor, roughness_factor, 0.0, 0.0]
+            base_color_factor,
+            emissive_factor,
+            occlusion_strength,
+            metallic_factor,
+            roughness_factor,
+            normal_scale,
+            alpha_cutoff,
         };
         let material_uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Material Uniform UB"),
@@ -37,13 +111,77 @@ impl Material {
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        let bind_group = Self::create_bind_group(
+            device,
+            layout,
+            [
+                &diffuse_texture,
+                &normal_texture,
+                &metallic_roughness_texture,
+                &emissive_texture,
+                &occlusion_texture,
+            ],
+            &material_uniform_buffer,
+        );
+
+        Self {
+            name: String::from(name),
+            diffuse_texture,
+            normal_texture,
+            metallic_roughness_texture,
+            emissive_texture,
+            occlusion_texture,
+            alpha_mode,
+            double_sided,
+            material_uniform,
+            material_uniform_buffer,
+            bind_group,
+        }
+    }
+
+    /// Fetches the samplers from `samplers` again and rebuilds the bind group,
+    /// after the sampler settings have changed.
+    pub fn update_samplers(&mut self, device: &wgpu::Device, layout: &wgpu::BindGroupLayout, samplers: &mut SamplerCache) {
+        for texture in [
+            &mut self.diffuse_texture,
+            &mut self.normal_texture,
+            &mut self.metallic_roughness_texture,
+            &mut self.emissive_texture,
+            &mut self.occlusion_texture,
+        ] {
+            texture.sampler = samplers.get(device, texture.sampler_key);
+        }
+
+        self.bind_group = Self::create_bind_group(
+            device,
+            layout,
+            [
+                &self.diffuse_texture,
+                &self.normal_texture,
+                &self.metallic_roughness_texture,
+                &self.emissive_texture,
+                &self.occlusion_texture,
+            ],
+            &self.material_uniform_buffer,
+        );
+    }
+
+    /// `textures` in binding order: diffuse, normal, metallic roughness,
+    /// emissive & occlusion.
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        textures: [&MaterialTexture; 5],
+        material_uniform_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        let [diffuse_texture, normal_texture, metallic_roughness_texture, emissive_texture, occlusion_texture] = textures;
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout,
             entries: &[
                 // diffuse
                 wgpu::BindGroupEntry {
                     binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
+                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.texture.view),
                 },
                 wgpu::BindGroupEntry {
                     binding: 1,
@@ -52,7 +190,7 @@ impl Material {
                 // normal
                 wgpu::BindGroupEntry {
                     binding: 2,
-                    resource: wgpu::BindingResource::TextureView(&normal_texture.view),
+                    resource: wgpu::BindingResource::TextureView(&normal_texture.texture.view),
                 },
                 wgpu::BindGroupEntry {
                     binding: 3,
@@ -61,7 +199,7 @@ impl Material {
                 // metallic roughness
                 wgpu::BindGroupEntry {
                     binding: 4,
-                    resource: wgpu::BindingResource::TextureView(&metallic_roughness_texture.view),
+                    resource: wgpu::BindingResource::TextureView(&metallic_roughness_texture.texture.view),
                 },
                 wgpu::BindGroupEntry {
                     binding: 5,
@@ -71,18 +209,27 @@ impl Material {
                 wgpu::BindGroupEntry {
                     binding: 6,
                     resource: material_uniform_buffer.as_entire_binding(),
-                }
+                },
+                // emissive
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: wgpu::BindingResource::TextureView(&emissive_texture.texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 8,
+                    resource: wgpu::BindingResource::Sampler(&emissive_texture.sampler),
+                },
+                // occlusion
+                wgpu::BindGroupEntry {
+                    binding: 9,
+                    resource: wgpu::BindingResource::TextureView(&occlusion_texture.texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 10,
+                    resource: wgpu::BindingResource::Sampler(&occlusion_texture.sampler),
+                },
             ],
             label: None,
-        });
-
-        Self {
-            name: String::from(name),
-            diffuse_texture,
-            normal_texture,
-            metallic_roughness_texture,
-            material_uniform,
-            bind_group,
-        }
+        })
     }
 }
\ No newline at end of file
@@ -1,3 +1,6 @@
+use std::collections::HashMap;
+
+use cgmath::{InnerSpace, Matrix, SquareMatrix, Transform};
 use wgpu::util::DeviceExt;
 use crate::core::model::ModelVertex;
 
@@ -7,6 +10,8 @@ pub struct Mesh {
     pub index_buffer: wgpu::Buffer,
     pub num_elements: u32,
     pub material: usize,
+    /// Center of the bounding box, for sorting.
+    pub center: [f32; 3],
 }
 
 impl Mesh {
@@ -14,13 +19,23 @@ impl Mesh {
         device: &wgpu::Device,
         buffers: &[gltf::buffer::Data],
         mesh: &gltf::Mesh,
-        name: &str) -> Vec<Mesh> {
+        transform: cgmath::Matrix4<f32>,
+        name: &str,
+        default_material: usize) -> Vec<Mesh> {
         let mut meshes = Vec::new();
 
         let primitives = mesh.primitives();
         primitives.for_each(|primitive| {
             let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
-            let material_index = primitive.material().index().unwrap_or(0);
+            let material_index = primitive.material().index().unwrap_or(default_material);
+
+            match primitive.mode() {
+                gltf::mesh::Mode::Triangles | gltf::mesh::Mode::TriangleStrip | gltf::mesh::Mode::TriangleFan => {}
+                mode => {
+                    println!("gltf: skipping {:?} primitive in mesh {:?}, only triangles are supported", mode, mesh.name());
+                    return;
+                }
+            }
 
             let mut vertices = Vec::new();
             let mut indices = Vec::new();
@@ -34,42 +49,38 @@ impl Mesh {
                     })
                 });
             } else {
-                panic!();
+                println!("gltf: skipping primitive without positions in mesh {:?}", mesh.name());
+                return;
             }
 
-            if let Some(normal_attribute) = reader.read_normals() {
+            let has_normals = if let Some(normal_attribute) = reader.read_normals() {
                 let mut normal_index = 0;
                 normal_attribute.for_each(|normal| {
                     // dbg!(normal);
                     vertices[normal_index].normal = normal;
                     normal_index += 1;
                 });
+                true
             } else {
-                panic!();
-            }
+                false
+            };
 
-            if let Some(tangent_attribute) = reader.read_tangents() {
+            let has_tangents = if let Some(tangent_attribute) = reader.read_tangents() {
                 // println!("gltf: loading tangents from file");
                 let mut tangent_index = 0;
                 tangent_attribute.for_each(|tangent| {
                     // dbg!(tangent);
-                    vertices[tangent_index].tangent = [
-                        tangent[0] * tangent[3],
-                        tangent[1] * tangent[3],
-                        tangent[2] * tangent[3],
-                    ];
-                    vertices[tangent_index].bitangent =
-                        cgmath::Vector3::from(vertices[tangent_index].normal)
-                            .cross(cgmath::Vector3::from(vertices[tangent_index].tangent))
-                            .into();
+                    vertices[tangent_index].tangent = tangent;
                     tangent_index += 1;
                 });
+                true
             } else {
-                println!("gltf: no tangents in file, calculating from tris");
-                Mesh::calc_tangents(&indices, &mut vertices);
-            }
+                false
+            };
 
-            if let Some(tex_coord_attribute) = reader.read_tex_coords(0).map(|v| v.into_f32()) {
+            // missing texcoords are left at zero
+            let tex_coord_set = Mesh::gltf_tex_coord_set(&primitive.material(), mesh.name());
+            if let Some(tex_coord_attribute) = reader.read_tex_coords(tex_coord_set).map(|v| v.into_f32()) {
                 let mut tex_coord_index = 0;
                 tex_coord_attribute.for_each(|tex_coord| {
                     // dbg!(tex_coord);
@@ -77,82 +88,274 @@ impl Mesh {
                     tex_coord_index += 1;
                 });
             } else {
-                panic!();
+                println!("gltf: no texcoords {} in mesh {:?}", tex_coord_set, mesh.name());
             }
 
             if let Some(indices_raw) = reader.read_indices() {
                 // dbg!(indices_raw);
                 indices.append(&mut indices_raw.into_u32().collect::<Vec<u32>>());
             } else {
-                panic!();
+                indices.extend(0..vertices.len() as u32);
             }
             // dbg!(indices);
 
-            let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Vertex Buffer", name)),
-                contents: bytemuck::cast_slice(&vertices),
-                usage: wgpu::BufferUsages::VERTEX,
-            });
-            let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Index Buffer", name)),
-                contents: bytemuck::cast_slice(&indices),
-                usage: wgpu::BufferUsages::INDEX,
-            });
-
-            meshes.push(Mesh {
-                name: name.to_string(),
-                vertex_buffer,
-                index_buffer,
-                num_elements: indices.len() as u32,
-                material: material_index,
-            });
+            match primitive.mode() {
+                gltf::mesh::Mode::TriangleStrip => indices = Mesh::strip_to_list(&indices),
+                gltf::mesh::Mode::TriangleFan => indices = Mesh::fan_to_list(&indices),
+                _ => {}
+            }
+            indices.truncate(indices.len() / 3 * 3);
+            if indices.iter().any(|&i| i as usize >= vertices.len()) {
+                println!("gltf: skipping primitive with out of range indices in mesh {:?}", mesh.name());
+                return;
+            }
+
+            if !has_normals {
+                println!("gltf: no normals in mesh {:?}, using flat normals", mesh.name());
+                Mesh::calc_flat_normals(&mut indices, &mut vertices);
+            }
+            // tangents from the file don't match generated normals
+            if !has_tangents || !has_normals {
+                println!("gltf: no tangents in mesh {:?}, generating MikkTSpace tangents", mesh.name());
+                Mesh::calc_tangents(&mut indices, &mut vertices);
+            }
+
+            Mesh::apply_transform(&mut vertices, &mut indices, transform);
+            meshes.push(Mesh::new(device, name, &vertices, &indices, material_index));
         });
 
         meshes
     }
 
-    pub fn calc_tangents(indices: &[u32], vertices: &mut Vec<ModelVertex>) {
-        // tangents and bitangents from triangles
-        let mut triangles_included = vec![0; vertices.len()];
-        for chunk in indices.chunks(3) {
-            let v0 = vertices[chunk[0] as usize];
-            let v1 = vertices[chunk[1] as usize];
-            let v2 = vertices[chunk[2] as usize];
+    /// The texcoord set the textures of `material` use, the base color's first.
+    /// Vertices have one set, textures using another are logged and sampled
+    /// with it anyway.
+    fn gltf_tex_coord_set(material: &gltf::Material, mesh_name: Option<&str>) -> u32 {
+        let pbr = material.pbr_metallic_roughness();
+        let sets = [
+            pbr.base_color_texture().map(|texture| texture.tex_coord()),
+            pbr.metallic_roughness_texture().map(|texture| texture.tex_coord()),
+            material.normal_texture().map(|texture| texture.tex_coord()),
+            material.occlusion_texture().map(|texture| texture.tex_coord()),
+            material.emissive_texture().map(|texture| texture.tex_coord()),
+        ];
+        let mut sets = sets.into_iter().flatten();
+        let set = sets.next().unwrap_or(0);
+        if sets.any(|other| other != set) {
+            println!(
+                "gltf: material {:?} of mesh {:?} uses several texcoord sets, only {} is supported",
+                material.name(),
+                mesh_name,
+                set
+            );
+        }
+        set
+    }
+
+    /// The mesh of an OBJ model using `material`. `None` for models without
+    /// faces or with indices past the vertices.
+    pub fn from_obj(device: &wgpu::Device, model: &tobj::Model, name: &str, material: usize) -> Option<Mesh> {
+        let mesh = &model.mesh;
+        if mesh.indices.len() < 3 {
+            println!("obj: skipping model {:?} without faces", model.name);
+            return None;
+        }
+
+        let has_normals = !mesh.normals.is_empty();
+        let has_tex_coords = !mesh.texcoords.is_empty();
+        if !has_tex_coords {
+            println!("obj: no texcoords in model {:?}", model.name);
+        }
+
+        let mut vertices = (0..mesh.positions.len() / 3)
+            .map(|i| ModelVertex {
+                position: [mesh.positions[i * 3], mesh.positions[i * 3 + 1], mesh.positions[i * 3 + 2]],
+                // OBJ has v going up
+                tex_coords: if has_tex_coords {
+                    [mesh.texcoords[i * 2], 1.0 - mesh.texcoords[i * 2 + 1]]
+                } else {
+                    [0.0; 2]
+                },
+                normal: if has_normals {
+                    [mesh.normals[i * 3], mesh.normals[i * 3 + 1], mesh.normals[i * 3 + 2]]
+                } else {
+                    [0.0; 3]
+                },
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        let mut indices = mesh.indices.clone();
+        indices.truncate(indices.len() / 3 * 3);
+        if indices.iter().any(|&i| i as usize >= vertices.len()) {
+            println!("obj: skipping model {:?} with out of range indices", model.name);
+            return None;
+        }
+
+        if !has_normals {
+            println!("obj: no normals in model {:?}, using flat normals", model.name);
+            Mesh::calc_flat_normals(&mut indices, &mut vertices);
+        }
+        Mesh::calc_tangents(&mut indices, &mut vertices);
+
+        Some(Mesh::new(device, name, &vertices, &indices, material))
+    }
+
+    /// Uploads the vertices & indices.
+    pub fn new(device: &wgpu::Device, name: &str, vertices: &[ModelVertex], indices: &[u32], material: usize) -> Mesh {
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Vertex Buffer", name)),
+            contents: bytemuck::cast_slice(vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Index Buffer", name)),
+            contents: bytemuck::cast_slice(indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        Mesh {
+            name: name.to_string(),
+            vertex_buffer,
+            index_buffer,
+            num_elements: indices.len() as u32,
+            material,
+            center: Mesh::calc_center(vertices),
+        }
+    }
+
+    /// Splits the triangles so they don't share vertices, and gives each one its face normal.
+    pub fn calc_flat_normals(indices: &mut Vec<u32>, vertices: &mut Vec<ModelVertex>) {
+        let mut flat_vertices = Vec::with_capacity(indices.len());
+        for triangle in indices.chunks_exact(3) {
+            let v0 = vertices[triangle[0] as usize];
+            let v1 = vertices[triangle[1] as usize];
+            let v2 = vertices[triangle[2] as usize];
+
+            let pos0 = cgmath::Vector3::from(v0.position);
+            let normal = (cgmath::Vector3::from(v1.position) - pos0).cross(cgmath::Vector3::from(v2.position) - pos0);
+            let normal = if normal.magnitude2() > 0.0 { normal.normalize() } else { cgmath::Vector3::unit_y() };
+
+            for mut v in [v0, v1, v2] {
+                v.normal = normal.into();
+                flat_vertices.push(v);
+            }
+        }
+
+        *indices = (0..flat_vertices.len() as u32).collect();
+        *vertices = flat_vertices;
+    }
+
+    pub fn calc_center(vertices: &[ModelVertex]) -> [f32; 3] {
+        let mut min = [f32::MAX; 3];
+        let mut max = [f32::MIN; 3];
+        for v in vertices {
+            for i in 0..3 {
+                min[i] = min[i].min(v.position[i]);
+                max[i] = max[i].max(v.position[i]);
+            }
+        }
+        [
+            (min[0] + max[0]) * 0.5,
+            (min[1] + max[1]) * 0.5,
+            (min[2] + max[2]) * 0.5,
+        ]
+    }
 
-            let pos0: cgmath::Vector3<f32> = v0.position.into();
-            let pos1: cgmath::Vector3<f32> = v1.position.into();
-            let pos2: cgmath::Vector3<f32> = v2.position.into();
+    fn strip_to_list(indices: &[u32]) -> Vec<u32> {
+        let mut list = Vec::new();
+        for (i, window) in indices.windows(3).enumerate() {
+            // every other triangle is wound the other way
+            if i % 2 == 0 {
+                list.extend([window[0], window[1], window[2]]);
+            } else {
+                list.extend([window[1], window[0], window[2]]);
+            }
+        }
+        list
+    }
 
-            let uv0: cgmath::Vector2<f32> = v0.tex_coords.into();
-            let uv1: cgmath::Vector2<f32> = v1.tex_coords.into();
-            let uv2: cgmath::Vector2<f32> = v2.tex_coords.into();
+    fn fan_to_list(indices: &[u32]) -> Vec<u32> {
+        let mut list = Vec::new();
+        for window in indices.get(1..).unwrap_or_default().windows(2) {
+            list.extend([indices[0], window[0], window[1]]);
+        }
+        list
+    }
 
-            let delta_pos1 = pos1 - pos0;
-            let delta_pos2 = pos2 - pos0;
+    /// Bakes `transform` into the vertices, flipping the winding if it mirrors the mesh.
+    pub fn apply_transform(vertices: &mut [ModelVertex], indices: &mut [u32], transform: cgmath::Matrix4<f32>) {
+        if transform == cgmath::Matrix4::identity() {
+            return;
+        }
 
-            let delta_uv1 = uv1 - uv0;
-            let delta_uv2 = uv2 - uv0;
+        let linear = cgmath::Matrix3::from_cols(
+            transform.x.truncate(),
+            transform.y.truncate(),
+            transform.z.truncate(),
+        );
+        let normal_matrix = linear.invert().map(|m| m.transpose()).unwrap_or(linear);
 
-            let r = 1.0 / (delta_uv1.x * delta_uv2.y - delta_uv1.y * delta_uv2.x);
-            let tangent = (delta_pos1 * delta_uv2.y - delta_pos2 * delta_uv1.y) * r;
-            let bitangent = (delta_pos2 * delta_uv1.x - delta_pos1 * delta_uv2.x) * -r;
+        let mirrored = linear.determinant() < 0.0;
+        for v in vertices.iter_mut() {
+            v.position = transform.transform_point(v.position.into()).into();
+            let normal = normal_matrix * cgmath::Vector3::from(v.normal);
+            if normal.magnitude2() > 0.0 {
+                v.normal = normal.normalize().into();
+            }
+            let tangent = linear * cgmath::Vector4::from(v.tangent).truncate();
+            let sign = if mirrored { -v.tangent[3] } else { v.tangent[3] };
+            v.tangent = tangent.extend(sign).into();
+        }
 
-            for i in chunk.iter().take(3) {
-                let sz = *i as usize;
-                vertices[sz].tangent =
-                    (tangent + cgmath::Vector3::from(vertices[sz].tangent)).into();
-                vertices[sz].bitangent =
-                    (bitangent + cgmath::Vector3::from(vertices[sz].bitangent)).into();
-                triangles_included[sz] += 1;
+        if mirrored {
+            for triangle in indices.chunks_exact_mut(3) {
+                triangle.swap(1, 2);
             }
         }
+    }
 
-        // Average the tangents/bitangents
-        for (i, n) in triangles_included.into_iter().enumerate() {
-            let denom = 1.0 / n as f32;
-            let v = &mut vertices[i];
-            v.tangent = (cgmath::Vector3::from(v.tangent) * denom).into();
-            v.bitangent = (cgmath::Vector3::from(v.bitangent) * denom).into();
+    /// Generates MikkTSpace tangents for the triangles. Vertices whose
+    /// triangles end up with different tangents, e.g. on mirrored UVs, are
+    /// split.
+    pub fn calc_tangents(indices: &mut Vec<u32>, vertices: &mut Vec<ModelVertex>) {
+        let mut geometry = TangentGeometry {
+            indices,
+            vertices,
+            tangents: vec![[0.0; 4]; indices.len()],
+        };
+        if !bevy_mikktspace::generate_tangents(&mut geometry) {
+            println!("mesh: MikkTSpace failed, using arbitrary tangents");
+            for v in vertices.iter_mut() {
+                let normal = cgmath::Vector3::from(v.normal);
+                let axis = if normal.x.abs() < 0.9 { cgmath::Vector3::unit_x() } else { cgmath::Vector3::unit_y() };
+                let tangent = axis.cross(normal).cross(normal);
+                let tangent = if tangent.magnitude2() > 0.0 { tangent.normalize() } else { axis };
+                v.tangent = tangent.extend(1.0).into();
+            }
+            return;
+        }
+        let tangents = geometry.tangents;
+
+        let original_count = vertices.len();
+        let mut assigned = vec![false; original_count];
+        let mut splits = HashMap::new();
+        for (index, tangent) in indices.iter_mut().zip(tangents) {
+            let vertex = *index as usize;
+            if !assigned[vertex] {
+                assigned[vertex] = true;
+                vertices[vertex].tangent = tangent;
+            } else if vertices[vertex].tangent != tangent {
+                let key = (*index, tangent.map(f32::to_bits));
+                *index = *splits.entry(key).or_insert_with(|| {
+                    let mut split = vertices[vertex];
+                    split.tangent = tangent;
+                    vertices.push(split);
+                    (vertices.len() - 1) as u32
+                });
+            }
+        }
+        if vertices.len() > original_count {
+            println!("mesh: split {} vertices for tangents", vertices.len() - original_count);
         }
     }
 
@@ -214,4 +417,44 @@ impl Mesh {
         }
     }
     */
+}
+
+/// The triangles of a mesh as seen by MikkTSpace, with a tangent for each
+/// corner.
+struct TangentGeometry<'a> {
+    indices: &'a [u32],
+    vertices: &'a [ModelVertex],
+    tangents: Vec<[f32; 4]>,
+}
+
+impl TangentGeometry<'_> {
+    fn vertex(&self, face: usize, vert: usize) -> &ModelVertex {
+        &self.vertices[self.indices[face * 3 + vert] as usize]
+    }
+}
+
+impl bevy_mikktspace::Geometry for TangentGeometry<'_> {
+    fn num_faces(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    fn num_vertices_of_face(&self, _face: usize) -> usize {
+        3
+    }
+
+    fn position(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.vertex(face, vert).position
+    }
+
+    fn normal(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.vertex(face, vert).normal
+    }
+
+    fn tex_coord(&self, face: usize, vert: usize) -> [f32; 2] {
+        self.vertex(face, vert).tex_coords
+    }
+
+    fn set_tangent_encoded(&mut self, tangent: [f32; 4], face: usize, vert: usize) {
+        self.tangents[face * 3 + vert] = tangent;
+    }
 }
\ No newline at end of file
@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+
+use crate::core::layouts;
+use crate::core::pass::RenderPass;
+
+/// Fills the mip chain of a texture on the GPU, rendering each level from the
+/// one above it.
+///
+/// sRGB textures are sampled & written through sRGB views, so the filtering
+/// happens in linear space.
+pub struct MipmapGenerator {
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    passes: HashMap<wgpu::TextureFormat, RenderPass>,
+}
+
+impl MipmapGenerator {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let [group] = layouts::mipmap_groups();
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &group.entries(),
+            label: Some("mipmap_bind_group_layout"),
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Mipmap Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Self {
+            bind_group_layout,
+            sampler,
+            passes: HashMap::new(),
+        }
+    }
+
+    /// Number of levels in a full mip chain for a texture of this size.
+    pub fn mip_level_count(width: u32, height: u32) -> u32 {
+        32 - width.max(height).max(1).leading_zeros()
+    }
+
+    /// Generates levels `1..mip_level_count` of `texture` from level 0. The
+    /// texture needs `TEXTURE_BINDING` & `RENDER_ATTACHMENT` usage.
+    pub fn generate(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, texture: &wgpu::Texture) {
+        let mip_level_count = texture.mip_level_count();
+        if mip_level_count < 2 {
+            return;
+        }
+
+        let format = texture.format();
+        let bind_group_layout = &self.bind_group_layout;
+        let pass = self.passes.entry(format).or_insert_with(|| {
+            RenderPass::new(
+                device,
+                &[bind_group_layout],
+                &[],
+                "mipmap.wgsl",
+                &[],
+                Some(format),
+                None,
+                &[],
+                "mipmap pass",
+                false,
+                false,
+                false,
+                None,
+            )
+        });
+
+        let views = (0..mip_level_count)
+            .map(|mip| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("Mipmap View"),
+                    base_mip_level: mip,
+                    mip_level_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Mipmap Encoder"),
+        });
+        for target_mip in 1..mip_level_count as usize {
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &self.bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&views[target_mip - 1]),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&self.sampler),
+                    },
+                ],
+                label: Some("mipmap_bind_group"),
+            });
+
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Mipmap Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &views[target_mip],
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            render_pass.set_pipeline(&pass.pipeline);
+            render_pass.set_bind_group(0, &bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+        queue.submit(Some(encoder.finish()));
+    }
+}
@@ -1,9 +1,18 @@
 pub mod camera;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod capture;
 pub mod instance;
+pub mod ktx;
+pub mod layouts;
 pub mod light;
+pub mod mipmap;
 pub mod model;
 pub mod pass;
 pub mod resources;
+pub mod scene;
+pub mod settings;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod shader_watcher;
 pub mod state;
 pub mod texture;
 pub mod window;
@@ -1,6 +1,7 @@
 use std::ops::Range;
 use crate::core::material::Material;
 use crate::core::mesh::Mesh;
+use crate::shaders::structs::{wgsl_struct, Usage};
 
 pub struct Model {
     pub meshes: Vec<Mesh>,
@@ -17,10 +18,16 @@ pub struct ModelVertex {
     pub position: [f32; 3],
     pub tex_coords: [f32; 2],
     pub normal: [f32; 3],
-    pub tangent: [f32; 3],
-    pub bitangent: [f32; 3],
+    /// MikkTSpace tangent, w is the sign of the bitangent.
+    pub tangent: [f32; 4],
 }
 
+wgsl_struct!(
+    ModelVertex as VertexInput,
+    Usage::Vertex { first_location: 0 },
+    [position, tex_coords, normal, tangent]
+);
+
 impl Vertex for ModelVertex {
     fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
         use std::mem;
@@ -50,13 +57,7 @@ impl Vertex for ModelVertex {
                 wgpu::VertexAttribute {
                     offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
                     shader_location: 3,
-                    format: wgpu::VertexFormat::Float32x3,
-                },
-                // bitangent
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 11]>() as wgpu::BufferAddress,
-                    shader_location: 4,
-                    format: wgpu::VertexFormat::Float32x3,
+                    format: wgpu::VertexFormat::Float32x4,
                 },
             ],
         }
@@ -1,22 +1,43 @@
 use wgpu::{
-    BindGroupLayout, Device, PushConstantRange, RenderPipeline, TextureFormat, VertexBufferLayout,
+    BindGroupLayout, Device, PipelineLayout, PushConstantRange, RenderPipeline, TextureFormat, VertexBufferLayout,
 };
 
-use crate::shaders::preprocessor::preprocess_wgsl;
+use crate::shaders::preprocessor::{preprocess_wgsl, PreprocessedShader};
 
 pub struct RenderPass {
     pub pipeline: RenderPipeline,
+    /// The shader and its includes, to know when to reload.
+    shader_files: Vec<String>,
+    layout: PipelineLayout,
+    config: PipelineConfig,
+}
+
+/// Everything besides the shader that goes into the pipeline, kept for
+/// reloading.
+struct PipelineConfig {
+    shader_name: String,
+    shader_defines: Vec<(String, String)>,
+    color_format: Option<TextureFormat>,
+    depth_format: Option<TextureFormat>,
+    vertex_layouts: Vec<VertexBufferLayout<'static>>,
+    label: String,
+    is_shadow: bool,
+    has_transparency: bool,
+    write_depth: bool,
+    cull_mode: Option<wgpu::Face>,
 }
 
 impl RenderPass {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         device: &Device,
         bind_group_layouts: &[&BindGroupLayout],
         push_constant_ranges: &[PushConstantRange],
         shader_name: &str,
+        shader_defines: &[(&str, &str)],
         color_format: Option<TextureFormat>,
         depth_format: Option<TextureFormat>,
-        vertex_layouts: &[VertexBufferLayout],
+        vertex_layouts: &[VertexBufferLayout<'static>],
         label: &str,
         is_shadow: bool,
         has_transparency: bool,
@@ -28,41 +49,96 @@ impl RenderPass {
             bind_group_layouts,
             push_constant_ranges,
         });
-        let shader = wgpu::ShaderModuleDescriptor {
-            label: Some(shader_name),
-            source: preprocess_wgsl(shader_name),
-        };
-        let pipeline = Self::create_render_pipeline(
-            device,
-            &layout,
+        let config = PipelineConfig {
+            shader_name: shader_name.to_string(),
+            shader_defines: shader_defines
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
             color_format,
             depth_format,
-            vertex_layouts,
-            shader,
-            label,
+            vertex_layouts: vertex_layouts.to_vec(),
+            label: label.to_string(),
             is_shadow,
             has_transparency,
             write_depth,
             cull_mode,
-        );
+        };
+        let shader = preprocess_wgsl(shader_name, shader_defines)
+            .unwrap_or_else(|e| panic!("preprocess_wgsl: {}", e));
+        // naga errors with the lines of the original files, wgpu would only
+        // have the merged source
+        if let Err(e) = shader.validate() {
+            panic!("{}: {}", label, e);
+        }
+        let pipeline = Self::create_render_pipeline(device, &layout, &config, &shader.source);
 
-        Self { pipeline }
+        Self {
+            pipeline,
+            shader_files: shader.files,
+            layout,
+            config,
+        }
+    }
+
+    pub fn shader_name(&self) -> &str {
+        &self.config.shader_name
+    }
+
+    /// The defines the shader was preprocessed with.
+    pub fn shader_defines(&self) -> Vec<(&str, &str)> {
+        self.config
+            .shader_defines
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect()
+    }
+
+    /// If the shader is `file` or includes it.
+    pub fn uses_shader_file(&self, file: &str) -> bool {
+        self.shader_files.iter().any(|f| f == file)
+    }
+
+    /// Rebuilds the pipeline from a new version of the shader. The old pipeline
+    /// is kept if the shader doesn't compile.
+    pub fn reload(&mut self, device: &Device, shader: PreprocessedShader) -> anyhow::Result<()> {
+        shader.validate().map_err(anyhow::Error::msg)?;
+
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let pipeline = Self::create_render_pipeline(device, &self.layout, &self.config, &shader.source);
+        if let Some(error) = pollster::block_on(device.pop_error_scope()) {
+            return Err(anyhow::anyhow!("{}", error));
+        }
+
+        self.pipeline = pipeline;
+        self.shader_files = shader.files;
+        Ok(())
     }
 
     fn create_render_pipeline(
         device: &wgpu::Device,
         layout: &wgpu::PipelineLayout,
-        color_format: Option<wgpu::TextureFormat>,
-        depth_format: Option<wgpu::TextureFormat>,
-        vertex_layouts: &[wgpu::VertexBufferLayout],
-        shader: wgpu::ShaderModuleDescriptor,
-        label: &str,
-        is_shadow: bool,
-        has_transparency: bool,
-        write_depth: bool,
-        cull_mode: Option<wgpu::Face>,
+        config: &PipelineConfig,
+        source: &str,
     ) -> wgpu::RenderPipeline {
-        let shader = device.create_shader_module(shader);
+        let PipelineConfig {
+            shader_name,
+            shader_defines: _,
+            color_format,
+            depth_format,
+            vertex_layouts,
+            label,
+            is_shadow,
+            has_transparency,
+            write_depth,
+            cull_mode,
+        } = config;
+        let (color_format, depth_format, is_shadow, has_transparency, write_depth, cull_mode) =
+            (*color_format, *depth_format, *is_shadow, *has_transparency, *write_depth, *cull_mode);
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(shader_name),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
 
         let blend_comp = if has_transparency {
             wgpu::BlendComponent {
@@ -74,22 +150,23 @@ impl RenderPass {
             wgpu::BlendComponent::REPLACE
         };
 
-        let fragment_targets = &[Some(wgpu::ColorTargetState {
-            format: color_format.unwrap_or(wgpu::TextureFormat::Bgra8Unorm),
+        let fragment_target = color_format.map(|format| wgpu::ColorTargetState {
+            format,
             blend: Some(wgpu::BlendState {
                 alpha: blend_comp,
                 color: blend_comp,
             }),
             write_mask: wgpu::ColorWrites::ALL,
-        })];
-        let fragment = match color_format {
-            Some(..) => Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                targets: fragment_targets,
-            }),
-            None => None,
-        };
+        });
+        // depth only passes still run the fragment shader, for alpha testing
+        let fragment = Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: match fragment_target {
+                Some(..) => std::slice::from_ref(&fragment_target),
+                None => &[],
+            },
+        });
 
         device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
             label: Some((label.to_owned() + " pipeline Layout").as_str()),
@@ -1,122 +1,489 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use cgmath::SquareMatrix;
+
 use rust_embed::RustEmbed;
 
+use crate::core::ktx::decode_ktx2;
 use crate::core::model::{Model};
 use crate::core::mesh::Mesh;
-use crate::core::material::Material;
-use crate::core::texture::Texture;
+use crate::core::mipmap::MipmapGenerator;
+use crate::core::material::{AlphaMode, Material, MaterialTexture};
+use crate::core::texture::{SamplerCache, SamplerKey, Texture};
+
+// Defaults for missing material maps, these leave the material factors as is.
+const DEFAULT_DIFFUSE: [u8; 4] = [255, 255, 255, 255];
+const DEFAULT_NORMAL: [u8; 4] = [128, 128, 255, 255];
+const DEFAULT_METALLIC_ROUGHNESS: [u8; 4] = [255, 255, 255, 255];
+const DEFAULT_EMISSIVE: [u8; 4] = [255, 255, 255, 255];
+const DEFAULT_OCCLUSION: [u8; 4] = [255, 255, 255, 255];
 
 #[derive(RustEmbed)]
 #[folder = "res"]
 struct Asset;
 
-pub fn load_string(file_name: &str) -> String {
-    let binary = Asset::get(file_name).unwrap();
-    std::str::from_utf8(binary.data.as_ref()).unwrap().to_owned()
+/// Where scenes and models are loaded from. Paths are always relative to the
+/// source, using `/` as the separator.
+#[derive(Debug, Clone, Default)]
+pub enum AssetSource {
+    /// The `res` directory, embedded into the binary.
+    #[default]
+    Embedded,
+    /// A directory on disk.
+    #[cfg(not(target_arch = "wasm32"))]
+    Directory(std::path::PathBuf),
+    /// Fetched over HTTP, relative to a base URL.
+    #[cfg(target_arch = "wasm32")]
+    Http(String),
+}
+
+#[derive(Debug)]
+pub enum AssetError {
+    NotFound(String),
+    Io(String, std::io::Error),
+    Http(String, String),
+    InvalidUtf8(String),
+}
+
+impl fmt::Display for AssetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssetError::NotFound(path) => write!(f, "asset not found: {}", path),
+            AssetError::Io(path, e) => write!(f, "failed to read asset {}: {}", path, e),
+            AssetError::Http(url, e) => write!(f, "failed to fetch asset {}: {}", url, e),
+            AssetError::InvalidUtf8(path) => write!(f, "asset is not valid UTF-8: {}", path),
+        }
+    }
+}
+
+impl std::error::Error for AssetError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AssetError::Io(_, e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Errors from loading a model.
+#[derive(Debug)]
+pub enum LoadError {
+    Asset(AssetError),
+    Gltf(gltf::Error),
+    Obj(tobj::LoadError),
+    Image(image::ImageError),
+    Texture(anyhow::Error),
+    /// A buffer is shorter than the file says.
+    BufferLength { buffer: usize, expected: usize, actual: usize },
+    /// A material references an image that doesn't exist.
+    MissingImage(usize),
+    /// The image data doesn't match its size & format.
+    InvalidImage(usize),
+    UnsupportedImage(String),
+    /// Not a .gltf, .glb or .obj file.
+    UnsupportedModel(String),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Asset(e) => write!(f, "{}", e),
+            LoadError::Gltf(e) => write!(f, "{}", e),
+            LoadError::Obj(e) => write!(f, "invalid OBJ: {}", e),
+            LoadError::Image(e) => write!(f, "failed to decode image: {}", e),
+            LoadError::Texture(e) => write!(f, "failed to create texture: {}", e),
+            LoadError::BufferLength { buffer, expected, actual } => {
+                write!(f, "buffer {} is {} bytes, expected {}", buffer, actual, expected)
+            }
+            LoadError::MissingImage(index) => write!(f, "image {} doesn't exist", index),
+            LoadError::InvalidImage(index) => write!(f, "image {} doesn't match its size", index),
+            LoadError::UnsupportedImage(e) => write!(f, "unsupported image: {}", e),
+            LoadError::UnsupportedModel(file_name) => write!(f, "unsupported model format: {}", file_name),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoadError::Asset(e) => Some(e),
+            LoadError::Gltf(e) => Some(e),
+            LoadError::Obj(e) => Some(e),
+            LoadError::Image(e) => Some(e),
+            LoadError::Texture(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<AssetError> for LoadError {
+    fn from(e: AssetError) -> Self {
+        LoadError::Asset(e)
+    }
+}
+
+impl From<gltf::Error> for LoadError {
+    fn from(e: gltf::Error) -> Self {
+        LoadError::Gltf(e)
+    }
+}
+
+impl From<tobj::LoadError> for LoadError {
+    fn from(e: tobj::LoadError) -> Self {
+        LoadError::Obj(e)
+    }
+}
+
+impl From<image::ImageError> for LoadError {
+    fn from(e: image::ImageError) -> Self {
+        LoadError::Image(e)
+    }
+}
+
+impl AssetSource {
+    /// `dir` next to the page the renderer runs in, `None` outside a browser
+    /// window.
+    #[cfg(target_arch = "wasm32")]
+    pub fn page_relative(dir: &str) -> Option<Self> {
+        let href = web_sys::window()?.location().href().ok()?;
+        let page = href.split(['?', '#']).next()?;
+        let base = &page[..page.rfind('/')? + 1];
+        Some(AssetSource::Http(format!("{}{}", base, dir)))
+    }
+
+    pub async fn load_binary(&self, file_name: &str) -> Result<Vec<u8>, AssetError> {
+        match self {
+            AssetSource::Embedded => load_embedded_binary(file_name),
+            #[cfg(not(target_arch = "wasm32"))]
+            AssetSource::Directory(dir) => {
+                let path = dir.join(file_name);
+                std::fs::read(&path).map_err(|e| match e.kind() {
+                    std::io::ErrorKind::NotFound => AssetError::NotFound(path.display().to_string()),
+                    _ => AssetError::Io(path.display().to_string(), e),
+                })
+            }
+            #[cfg(target_arch = "wasm32")]
+            AssetSource::Http(base_url) => {
+                let url = format!("{}/{}", base_url.trim_end_matches('/'), file_name);
+                fetch_binary(&url)
+                    .await
+                    .map_err(|e| AssetError::Http(url, format!("{:?}", e)))
+            }
+        }
+    }
+
+    pub async fn load_string(&self, file_name: &str) -> Result<String, AssetError> {
+        let binary = self.load_binary(file_name).await?;
+        String::from_utf8(binary).map_err(|_| AssetError::InvalidUtf8(file_name.to_string()))
+    }
+}
+
+fn load_embedded_binary(file_name: &str) -> Result<Vec<u8>, AssetError> {
+    Asset::get(file_name)
+        .map(|file| file.data.into_owned())
+        .ok_or_else(|| AssetError::NotFound(file_name.to_string()))
+}
+
+/// Loads a string from the embedded assets, for files that are always
+/// compiled in, such as shaders.
+pub fn load_string(file_name: &str) -> Result<String, AssetError> {
+    let binary = load_embedded_binary(file_name)?;
+    String::from_utf8(binary).map_err(|_| AssetError::InvalidUtf8(file_name.to_string()))
+}
+
+#[cfg(target_arch = "wasm32")]
+async fn fetch_binary(url: &str) -> Result<Vec<u8>, wasm_bindgen::JsValue> {
+    use wasm_bindgen::JsCast;
+    use wasm_bindgen_futures::JsFuture;
+
+    let window = web_sys::window().ok_or("no window")?;
+    let response: web_sys::Response = JsFuture::from(window.fetch_with_str(url))
+        .await?
+        .dyn_into()?;
+    if !response.ok() {
+        return Err(format!("status {}", response.status()).into());
+    }
+    let buffer = JsFuture::from(response.array_buffer()?).await?;
+    Ok(js_sys::Uint8Array::new(&buffer).to_vec())
+}
+
+/// Resolves `uri` relative to the directory of `file_name`.
+fn resolve_path(file_name: &str, uri: &str) -> String {
+    let mut parts: Vec<&str> = file_name.split('/').collect();
+    parts.pop();
+    for part in uri.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+/// Decodes `%XX` escapes in a relative glTF URI.
+fn percent_decode(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes.get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], escaped) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// A model image, decoded unless it's a KTX2 file.
+enum GltfImage {
+    Pixels(gltf::image::Data),
+    Ktx2(Vec<u8>),
+}
+
+struct GltfImport {
+    document: gltf::Document,
+    buffers: Vec<gltf::buffer::Data>,
+    images: Vec<GltfImage>,
+    /// The `KHR_texture_basisu` image of each texture.
+    ktx2_sources: Vec<Option<usize>>,
+}
+
+/// Like `gltf::import_slice`, but resolves external buffers & images relative to
+/// `file_name` in `source`.
+async fn import_gltf(source: &AssetSource, file_name: &str) -> Result<GltfImport, LoadError> {
+    let binary = source.load_binary(file_name).await?;
+    let (json, mut blob) = if binary.starts_with(b"glTF") {
+        let glb = gltf::binary::Glb::from_slice(&binary)?;
+        (glb.json.into_owned(), glb.bin.map(|bin| bin.into_owned()))
+    } else {
+        (binary, None)
+    };
+
+    let mut json: gltf::json::Value = gltf::json::deserialize::from_slice(&json).map_err(gltf::Error::Deserialize)?;
+    let ktx2_sources = resolve_texture_basisu(&mut json);
+    let root = gltf::json::deserialize::from_value(json).map_err(gltf::Error::Deserialize)?;
+    let document = gltf::Document::from_json(root)?;
+
+    let mut buffers = Vec::new();
+    for buffer in document.buffers() {
+        let data = match buffer.source() {
+            gltf::buffer::Source::Uri(uri) if !uri.starts_with("data:") => {
+                let mut data = source.load_binary(&resolve_path(file_name, &percent_decode(uri))).await?;
+                while data.len() % 4 != 0 {
+                    data.push(0);
+                }
+                gltf::buffer::Data(data)
+            }
+            // embedded data & glb blob
+            buffer_source => gltf::buffer::Data::from_source_and_blob(buffer_source, None, &mut blob)?,
+        };
+        if data.len() < buffer.length() {
+            return Err(LoadError::BufferLength {
+                buffer: buffer.index(),
+                expected: buffer.length(),
+                actual: data.len(),
+            });
+        }
+        buffers.push(data);
+    }
+
+    let mut images = Vec::new();
+    for image in document.images() {
+        let data = match image.source() {
+            gltf::image::Source::Uri { uri, mime_type } if !uri.starts_with("data:") => {
+                let encoded = source.load_binary(&resolve_path(file_name, &percent_decode(uri))).await?;
+                if mime_type == Some(KTX2_MIME_TYPE) || uri.ends_with(".ktx2") {
+                    GltfImage::Ktx2(encoded)
+                } else {
+                    GltfImage::Pixels(decode_gltf_image(&encoded)?)
+                }
+            }
+            gltf::image::Source::View { view, mime_type } if mime_type == KTX2_MIME_TYPE => {
+                let bytes = buffers[view.buffer().index()]
+                    .get(view.offset()..view.offset() + view.length())
+                    .ok_or(LoadError::InvalidImage(image.index()))?;
+                GltfImage::Ktx2(bytes.to_vec())
+            }
+            // gltf only decodes data uris if given a base path, it's never used for them
+            image_source => GltfImage::Pixels(gltf::image::Data::from_source(
+                image_source,
+                Some(std::path::Path::new("")),
+                &buffers,
+            )?),
+        };
+        images.push(data);
+    }
+
+    Ok(GltfImport {
+        document,
+        buffers,
+        images,
+        ktx2_sources,
+    })
+}
+
+const KTX2_MIME_TYPE: &str = "image/ktx2";
+const TEXTURE_BASISU: &str = "KHR_texture_basisu";
+
+/// gltf doesn't know `KHR_texture_basisu`, so it's resolved in the JSON before
+/// parsing. Returns the KTX2 image of each texture, `source` is kept as the
+/// fallback if there is one.
+fn resolve_texture_basisu(json: &mut gltf::json::Value) -> Vec<Option<usize>> {
+    let mut ktx2_sources = Vec::new();
+    if let Some(textures) = json.get_mut("textures").and_then(|textures| textures.as_array_mut()) {
+        for texture in textures {
+            let ktx2_source = texture
+                .pointer(&format!("/extensions/{}/source", TEXTURE_BASISU))
+                .and_then(|source| source.as_u64());
+            if let (Some(ktx2_source), Some(texture)) = (ktx2_source, texture.as_object_mut()) {
+                texture.entry("source").or_insert(ktx2_source.into());
+            }
+            ktx2_sources.push(ktx2_source.map(|source| source as usize));
+        }
+    }
+    if let Some(required) = json.get_mut("extensionsRequired").and_then(|required| required.as_array_mut()) {
+        required.retain(|extension| *extension != TEXTURE_BASISU);
+    }
+    ktx2_sources
+}
+
+fn decode_gltf_image(encoded: &[u8]) -> Result<gltf::image::Data, LoadError> {
+    let image = image::load_from_memory(encoded)?;
+    let format = match image {
+        image::DynamicImage::ImageLuma8(_) => gltf::image::Format::R8,
+        image::DynamicImage::ImageLumaA8(_) => gltf::image::Format::R8G8,
+        image::DynamicImage::ImageRgb8(_) => gltf::image::Format::R8G8B8,
+        image::DynamicImage::ImageRgba8(_) => gltf::image::Format::R8G8B8A8,
+        image::DynamicImage::ImageLuma16(_) => gltf::image::Format::R16,
+        image::DynamicImage::ImageLumaA16(_) => gltf::image::Format::R16G16,
+        image::DynamicImage::ImageRgb16(_) => gltf::image::Format::R16G16B16,
+        image::DynamicImage::ImageRgba16(_) => gltf::image::Format::R16G16B16A16,
+        image::DynamicImage::ImageRgb32F(_) => gltf::image::Format::R32G32B32FLOAT,
+        image::DynamicImage::ImageRgba32F(_) => gltf::image::Format::R32G32B32A32FLOAT,
+        _ => return Err(LoadError::UnsupportedImage(format!("color type {:?}", image.color()))),
+    };
+    Ok(gltf::image::Data {
+        width: image.width(),
+        height: image.height(),
+        format,
+        pixels: image.into_bytes(),
+    })
+}
+
+/// Loads a glTF or OBJ model, depending on the file extension.
+pub async fn load_model(
+    source: &AssetSource,
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+    samplers: &mut SamplerCache,
+    mipmaps: &mut MipmapGenerator,
+) -> Result<Model, LoadError> {
+    let extension = file_name.rsplit_once('.').map(|(_, extension)| extension.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("gltf") | Some("glb") => load_model_gltf(source, file_name, device, queue, layout, samplers, mipmaps).await,
+        Some("obj") => load_model_obj(source, file_name, device, queue, layout, samplers, mipmaps).await,
+        _ => Err(LoadError::UnsupportedModel(file_name.to_string())),
+    }
 }
 
 pub async fn load_model_gltf(
+    source: &AssetSource,
     file_name: &str,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
     layout: &wgpu::BindGroupLayout,
-) -> anyhow::Result<Model> {
+    samplers: &mut SamplerCache,
+    mipmaps: &mut MipmapGenerator,
+) -> Result<Model, LoadError> {
     let mut materials = Vec::new();
     let mut meshes = Vec::new();
 
     println!("gltf: Loading file {}", file_name);
-    let binary = Asset::get(file_name).unwrap();
-    let (document, buffers, mut images) = gltf::import_slice(binary.data.as_ref())?;
+    let GltfImport {
+        document,
+        buffers,
+        images,
+        ktx2_sources,
+    } = import_gltf(source, file_name).await?;
+
+    // appended after the materials of the file
+    let default_material = document.materials().len();
 
     println!("gltf: Loading meshes");
-    for mesh in document.meshes() {
-        meshes.extend(Mesh::from_gltf(device, &buffers, &mesh, file_name));
+    match document.default_scene().or_else(|| document.scenes().next()) {
+        Some(scene) => {
+            for node in scene.nodes() {
+                load_node_meshes(device, &buffers, &node, cgmath::Matrix4::identity(), file_name, default_material, &mut meshes);
+            }
+        }
+        // no scenes, nothing to place the meshes with
+        None => {
+            for mesh in document.meshes() {
+                meshes.extend(Mesh::from_gltf(device, &buffers, &mesh, cgmath::Matrix4::identity(), file_name, default_material));
+            }
+        }
     }
 
     println!("gltf: Loading materials");
+    let mut textures = TextureLoader {
+        device,
+        queue,
+        samplers,
+        mipmaps,
+        images: &images,
+        ktx2_sources: &ktx2_sources,
+        label: file_name,
+        image_textures: HashMap::new(),
+        color_textures: HashMap::new(),
+    };
     for material in document.materials() {
         let pbr = material.pbr_metallic_roughness();
 
         // diffuse
-        let diffuse_index = pbr
-            .base_color_texture()
-            .map(|tex| {
-                // println!("gltf: get diffuse tex");
-                tex.texture().source().index()
-            })
-            .unwrap_or(0); // TODO default tex
-
-        let diffuse_data = &mut images[diffuse_index];
-
-        let diffuse_channels = diffuse_data.pixels.len() / (diffuse_data.width * diffuse_data.height) as usize;
-        assert!(diffuse_channels == 3 || diffuse_channels == 4, "gltf: diffuse tex unsupported channels count {:?}", diffuse_channels);
-        if diffuse_channels == 3 {
-            diffuse_data.pixels = gltf_pixels_to_wgpu(diffuse_data.pixels.clone(), diffuse_data.format);
-        }
-
-        let diffuse_texture = Texture::from_pixels(
-            device,
-            queue,
-            &diffuse_data.pixels,
-            (diffuse_data.width, diffuse_data.height),
-            gltf_image_format_stride(diffuse_data.format),
-            gltf_image_format_to_wgpu(diffuse_data.format, true),
-            Some(file_name),
-        )
-            .unwrap();
+        let diffuse_texture = match pbr.base_color_texture() {
+            Some(tex) => textures.texture(&tex.texture(), true)?,
+            None => textures.color(DEFAULT_DIFFUSE, true)?,
+        };
 
         // normal
-        let normal_index = material
-            .normal_texture()
-            .map(|tex| {
-                // println!("gltf: get normal tex");
-                tex.texture().source().index()
-            })
-            .unwrap_or(0); // TODO default tex
-
-        let normal_data = &mut images[normal_index];
-        let normal_channels = normal_data.pixels.len() / (normal_data.width * normal_data.height) as usize;
-        assert!(normal_channels == 3 || normal_channels == 4, "gltf: normal tex unsupported channels count {:?}", normal_channels);
-        if normal_channels == 3 {
-            normal_data.pixels = gltf_pixels_to_wgpu(normal_data.pixels.clone(), normal_data.format);
-        }
-
-        let normal_texture = Texture::from_pixels(
-            device,
-            queue,
-            &normal_data.pixels,
-            (normal_data.width, normal_data.height),
-            gltf_image_format_stride(normal_data.format),
-            gltf_image_format_to_wgpu(normal_data.format, false),
-            Some(file_name),
-        )
-            .unwrap();
+        let normal_texture = match material.normal_texture() {
+            Some(tex) => textures.texture(&tex.texture(), false)?,
+            None => textures.color(DEFAULT_NORMAL, false)?,
+        };
 
         // roughness-metalness
-        let rm_index = pbr
-            .metallic_roughness_texture()
-            .map(|tex| {
-                // println!("gltf: get roughness metalness tex");
-                tex.texture().source().index()
-            })
-            .unwrap_or(0); // TODO default tex
-
-        let rm_data = &mut images[rm_index];
-        let rm_channels = rm_data.pixels.len() / (rm_data.width * rm_data.height) as usize;
-        assert!(rm_channels == 3 || rm_channels == 4, "gltf: rm tex unsupported channels count {:?}", rm_channels);
-        if rm_channels == 3 {
-            rm_data.pixels = gltf_pixels_to_wgpu(rm_data.pixels.clone(), rm_data.format);
-        }
-
-        let rm_texture = Texture::from_pixels(
-            device,
-            queue,
-            &rm_data.pixels,
-            (rm_data.width, rm_data.height),
-            gltf_image_format_stride(rm_data.format),
-            gltf_image_format_to_wgpu(rm_data.format, false),
-            Some(file_name),
-        )
-            .unwrap();
+        let rm_texture = match pbr.metallic_roughness_texture() {
+            Some(tex) => textures.texture(&tex.texture(), false)?,
+            None => textures.color(DEFAULT_METALLIC_ROUGHNESS, false)?,
+        };
+
+        // emissive
+        let emissive_texture = match material.emissive_texture() {
+            Some(tex) => textures.texture(&tex.texture(), true)?,
+            None => textures.color(DEFAULT_EMISSIVE, true)?,
+        };
+
+        // occlusion
+        let occlusion_texture = match material.occlusion_texture() {
+            Some(tex) => textures.texture(&tex.texture(), false)?,
+            None => textures.color(DEFAULT_OCCLUSION, false)?,
+        };
 
         materials.push(Material::new(
             device,
@@ -124,81 +491,438 @@ pub async fn load_model_gltf(
             diffuse_texture,
             normal_texture,
             rm_texture,
+            emissive_texture,
+            occlusion_texture,
+            pbr.base_color_factor(),
+            material.emissive_factor(),
             pbr.metallic_factor(),
             pbr.roughness_factor(),
+            material.normal_texture().map_or(1.0, |tex| tex.scale()),
+            material.occlusion_texture().map_or(1.0, |tex| tex.strength()),
+            match material.alpha_mode() {
+                gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+                gltf::material::AlphaMode::Mask => AlphaMode::Mask,
+                gltf::material::AlphaMode::Blend => AlphaMode::Blend,
+            },
+            material.alpha_cutoff().unwrap_or(0.5),
+            material.double_sided(),
             layout,
         ));
     }
 
+    // for primitives without a material, with the defaults of the glTF spec
+    materials.push(Material::new(
+        device,
+        "Default Material",
+        textures.color(DEFAULT_DIFFUSE, true)?,
+        textures.color(DEFAULT_NORMAL, false)?,
+        textures.color(DEFAULT_METALLIC_ROUGHNESS, false)?,
+        textures.color(DEFAULT_EMISSIVE, true)?,
+        textures.color(DEFAULT_OCCLUSION, false)?,
+        [1.0; 4],
+        [0.0; 3],
+        1.0,
+        1.0,
+        1.0,
+        1.0,
+        AlphaMode::Opaque,
+        0.5,
+        false,
+        layout,
+    ));
+
     println!("gltf: load done!");
 
     Ok(Model { meshes, materials })
 }
 
-fn gltf_image_format_to_wgpu(format: gltf::image::Format, srgb: bool) -> wgpu::TextureFormat {
-    if srgb {
-        return match format {
-            gltf::image::Format::R8 => panic!(),
-            gltf::image::Format::R8G8 => panic!(),
-            gltf::image::Format::R8G8B8 => wgpu::TextureFormat::Rgba8UnormSrgb, // gltf_pixels_to_wgpu
-            gltf::image::Format::R8G8B8A8 => wgpu::TextureFormat::Rgba8UnormSrgb,
-            gltf::image::Format::R16 => panic!(),
-            gltf::image::Format::R16G16 => panic!(),
-            gltf::image::Format::R16G16B16 => panic!(), // gltf_pixels_to_wgpu
-            gltf::image::Format::R16G16B16A16 => panic!(),
-            gltf::image::Format::R32G32B32FLOAT => panic!(),
-            gltf::image::Format::R32G32B32A32FLOAT => panic!(),
+/// Loads an OBJ model and its MTL materials, mapped to the PBR material model
+/// as well as they can be:
+/// - `Kd` & `map_Kd` are the base color, with `d` as the alpha
+/// - `Ns` is converted to roughness, materials without `Ks` are fully rough
+/// - `Pr` & `Pm` set the roughness & metallic factors if present
+/// - `Ke` & `map_Ke` are the emission
+/// - `map_Bump` / `bump` / `norm` is used as the normal map
+/// - `map_Ka` is used as the occlusion map
+pub async fn load_model_obj(
+    source: &AssetSource,
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+    samplers: &mut SamplerCache,
+    mipmaps: &mut MipmapGenerator,
+) -> Result<Model, LoadError> {
+    println!("obj: Loading file {}", file_name);
+    let text = source.load_string(file_name).await?;
+    let (models, obj_materials) = tobj::load_obj_buf_async(
+        &mut std::io::Cursor::new(text),
+        &tobj::GPU_LOAD_OPTIONS,
+        |mtl_path| async move {
+            let mtl_path = resolve_path(file_name, &mtl_path);
+            match source.load_string(&mtl_path).await {
+                Ok(mtl) => tobj::load_mtl_buf(&mut std::io::Cursor::new(mtl)),
+                Err(e) => {
+                    println!("obj: can't load materials {} ({})", mtl_path, e);
+                    Err(tobj::LoadError::OpenFileFailed)
+                }
+            }
+        },
+    )
+        .await?;
+    // the meshes still load without their materials
+    let obj_materials = obj_materials.unwrap_or_default();
+
+    println!("obj: Loading meshes");
+    let meshes = models
+        .iter()
+        .filter_map(|model| {
+            // no usemtl, or one the MTL files don't have: the default
+            // material after the loaded ones
+            let material = model
+                .mesh
+                .material_id
+                .filter(|&material| material < obj_materials.len())
+                .unwrap_or(obj_materials.len());
+            Mesh::from_obj(device, model, file_name, material)
+        })
+        .collect::<Vec<_>>();
+
+    println!("obj: Loading textures");
+    // None for maps that can't be loaded, their slots get the default color
+    let mut image_indices = HashMap::new();
+    let mut images = Vec::new();
+    for material in &obj_materials {
+        let textures = [
+            &material.diffuse_texture,
+            &material.normal_texture,
+            &material.ambient_texture,
+            &material.unknown_param.get("map_Ke").cloned(),
+        ];
+        for texture in textures.into_iter().flatten() {
+            let path = resolve_path(file_name, &mtl_texture_path(texture));
+            if image_indices.contains_key(&path) {
+                continue;
+            }
+            let image = match source.load_binary(&path).await {
+                Ok(encoded) if path.ends_with(".ktx2") => Ok(GltfImage::Ktx2(encoded)),
+                Ok(encoded) => decode_gltf_image(&encoded).map(GltfImage::Pixels),
+                Err(e) => Err(e.into()),
+            };
+            match image {
+                Ok(image) => {
+                    image_indices.insert(path, Some(images.len()));
+                    images.push(image);
+                }
+                Err(e) => {
+                    println!("obj: can't load texture {} ({}), using the default", path, e);
+                    image_indices.insert(path, None);
+                }
+            }
+        }
+    }
+
+    println!("obj: Loading materials");
+    let mut textures = TextureLoader {
+        device,
+        queue,
+        samplers,
+        mipmaps,
+        images: &images,
+        ktx2_sources: &[],
+        label: file_name,
+        image_textures: HashMap::new(),
+        color_textures: HashMap::new(),
+    };
+    let mut texture = |texture: Option<&String>, default: [u8; 4], srgb: bool| {
+        let path = match texture {
+            Some(texture) => resolve_path(file_name, &mtl_texture_path(texture)),
+            None => return textures.color(default, srgb),
         };
+        match image_indices[&path].map(|index| textures.image_texture(index, srgb)) {
+            Some(Ok(texture)) => Ok(texture),
+            Some(Err(e)) => {
+                println!("obj: can't load texture {} ({}), using the default", path, e);
+                textures.color(default, srgb)
+            }
+            // logged when it couldn't be read
+            None => textures.color(default, srgb),
+        }
+    };
+
+    let mut materials = Vec::new();
+    for material in &obj_materials {
+        let param = |name: &str| material.unknown_param.get(name);
+        let param_floats = |name: &str| {
+            param(name).map(|value| value.split_whitespace().filter_map(|v| v.parse::<f32>().ok()).collect::<Vec<_>>())
+        };
+
+        let diffuse = material.diffuse.unwrap_or([1.0; 3]);
+        let specular = material.specular.unwrap_or([0.0; 3]);
+        let roughness = match param_floats("Pr").and_then(|v| v.first().copied()) {
+            Some(roughness) => roughness,
+            None if specular == [0.0; 3] => 1.0,
+            // Blinn-Phong exponent to GGX roughness
+            None => (2.0 / (material.shininess.unwrap_or(0.0).max(0.0) + 2.0)).sqrt(),
+        };
+        let metallic = param_floats("Pm").and_then(|v| v.first().copied()).unwrap_or(0.0);
+        let emissive = match param_floats("Ke") {
+            Some(v) if v.len() >= 3 => [v[0], v[1], v[2]],
+            _ if param("map_Ke").is_some() => [1.0; 3],
+            _ => [0.0; 3],
+        };
+        let alpha = material.dissolve.unwrap_or(1.0);
+
+        materials.push(Material::new(
+            device,
+            &material.name,
+            texture(material.diffuse_texture.as_ref(), DEFAULT_DIFFUSE, true)?,
+            texture(material.normal_texture.as_ref(), DEFAULT_NORMAL, false)?,
+            texture(None, DEFAULT_METALLIC_ROUGHNESS, false)?,
+            texture(param("map_Ke"), DEFAULT_EMISSIVE, true)?,
+            texture(material.ambient_texture.as_ref(), DEFAULT_OCCLUSION, false)?,
+            [diffuse[0], diffuse[1], diffuse[2], alpha],
+            emissive,
+            metallic,
+            roughness.clamp(0.0, 1.0),
+            1.0,
+            1.0,
+            if alpha < 1.0 { AlphaMode::Blend } else { AlphaMode::Opaque },
+            0.5,
+            false,
+            layout,
+        ));
     }
 
-    match format {
-        gltf::image::Format::R8 => wgpu::TextureFormat::R8Unorm,
-        gltf::image::Format::R8G8 => wgpu::TextureFormat::Rg8Unorm,
-        gltf::image::Format::R8G8B8 => wgpu::TextureFormat::Rgba8Unorm, // gltf_pixels_to_wgpu
-        gltf::image::Format::R8G8B8A8 => wgpu::TextureFormat::Rgba8Unorm,
-        gltf::image::Format::R16 => wgpu::TextureFormat::R16Unorm,
-        gltf::image::Format::R16G16 => wgpu::TextureFormat::Rg16Unorm,
-        gltf::image::Format::R16G16B16 => wgpu::TextureFormat::Rgba16Unorm, // gltf_pixels_to_wgpu
-        gltf::image::Format::R16G16B16A16 => wgpu::TextureFormat::Rgba16Unorm,
-        gltf::image::Format::R32G32B32FLOAT => wgpu::TextureFormat::Rgba32Float,
-        gltf::image::Format::R32G32B32A32FLOAT => wgpu::TextureFormat::Rgba32Float,
-    }
-}
-
-fn gltf_image_format_stride(format: gltf::image::Format) -> u32 {
-    match format {
-        gltf::image::Format::R8 => 1,
-        gltf::image::Format::R8G8 => 2,
-        gltf::image::Format::R8G8B8 => 4, // gltf_pixels_to_wgpu
-        gltf::image::Format::R8G8B8A8 => 4,
-        gltf::image::Format::R16 => 2,
-        gltf::image::Format::R16G16 => 4,
-        gltf::image::Format::R16G16B16 => 8, // gltf_pixels_to_wgpu
-        gltf::image::Format::R16G16B16A16 => 8,
-        gltf::image::Format::R32G32B32FLOAT => 12,
-        gltf::image::Format::R32G32B32A32FLOAT => 16,
-    }
-}
-
-// Add alpha if needed
-fn gltf_pixels_to_wgpu(mut bytes: Vec<u8>, format: gltf::image::Format) -> Vec<u8> {
-    if format == gltf::image::Format::R8G8B8 {
-        let pixels = bytes.len() / 3;
-        bytes.reserve_exact(pixels);
-        bytes = bytes
-            .chunks_exact(3)
-            .flat_map(|s| [s[0], s[1], s[2], 255])
-            .collect();
-    } else if format == gltf::image::Format::R16G16B16 {
-        let pixels = bytes.len() / 6;
-        bytes.reserve_exact(pixels);
-        bytes = bytes
-            .chunks_exact(6)
-            .flat_map(|s| [s[0], s[1], s[2], s[3], s[4], s[5], 255, 255])
-            .collect();
+    // for meshes without a material
+    materials.push(Material::new(
+        device,
+        "Default Material",
+        texture(None, DEFAULT_DIFFUSE, true)?,
+        texture(None, DEFAULT_NORMAL, false)?,
+        texture(None, DEFAULT_METALLIC_ROUGHNESS, false)?,
+        texture(None, [0; 4], true)?,
+        texture(None, DEFAULT_OCCLUSION, false)?,
+        [1.0; 4],
+        [0.0; 3],
+        0.0,
+        1.0,
+        1.0,
+        1.0,
+        AlphaMode::Opaque,
+        0.5,
+        false,
+        layout,
+    ));
+
+    println!("obj: load done!");
+
+    Ok(Model { meshes, materials })
+}
+
+/// The file name of an MTL texture statement, without the options before it.
+fn mtl_texture_path(texture: &str) -> String {
+    let path = if texture.starts_with('-') {
+        texture.split_whitespace().last().unwrap_or(texture)
     } else {
-        panic!("gltf_pixels_to_wgpu: Unsupported format {:?}", format);
+        texture
+    };
+    path.replace('\\', "/")
+}
+
+/// Loads the meshes of `node` and its children, with their world transforms baked in.
+fn load_node_meshes(
+    device: &wgpu::Device,
+    buffers: &[gltf::buffer::Data],
+    node: &gltf::Node,
+    parent_transform: cgmath::Matrix4<f32>,
+    name: &str,
+    default_material: usize,
+    meshes: &mut Vec<Mesh>,
+) {
+    let transform = parent_transform * cgmath::Matrix4::from(node.transform().matrix());
+    if let Some(mesh) = node.mesh() {
+        meshes.extend(Mesh::from_gltf(device, buffers, &mesh, transform, name, default_material));
+    }
+    for child in node.children() {
+        load_node_meshes(device, buffers, &child, transform, name, default_material, meshes);
+    }
+}
+
+/// Creates the material textures of one model file. Images & default colors
+/// are uploaded once, materials using the same ones share the `Texture`.
+struct TextureLoader<'a> {
+    device: &'a wgpu::Device,
+    queue: &'a wgpu::Queue,
+    samplers: &'a mut SamplerCache,
+    mipmaps: &'a mut MipmapGenerator,
+    images: &'a [GltfImage],
+    ktx2_sources: &'a [Option<usize>],
+    label: &'a str,
+    /// Keyed by image index & sRGB.
+    image_textures: HashMap<(usize, bool), Arc<Texture>>,
+    /// Keyed by color & sRGB.
+    color_textures: HashMap<([u8; 4], bool), Arc<Texture>>,
+}
+
+impl<'a> TextureLoader<'a> {
+    /// `texture` with its glTF sampler. Prefers the `KHR_texture_basisu`
+    /// image, falling back to `source` if that can't be loaded.
+    fn texture(&mut self, texture: &gltf::Texture, srgb: bool) -> Result<MaterialTexture, LoadError> {
+        let sampler_key = gltf_sampler_key(&texture.sampler());
+        let source = texture.source().index();
+
+        let ktx2_source = self.ktx2_sources.get(texture.index()).copied().flatten();
+        if let Some(ktx2_source) = ktx2_source.filter(|&index| index != source) {
+            match self.image(ktx2_source, srgb) {
+                Ok(image) => return Ok(MaterialTexture::new(self.device, image, self.samplers, sampler_key)),
+                Err(e) => println!("gltf: can't load KTX2 image {} ({}), using image {}", ktx2_source, e, source),
+            }
+        }
+
+        let image = self.image(source, srgb)?;
+        Ok(MaterialTexture::new(self.device, image, self.samplers, sampler_key))
+    }
+
+    /// Image `index` with the default sampler, for formats without samplers.
+    fn image_texture(&mut self, index: usize, srgb: bool) -> Result<MaterialTexture, LoadError> {
+        let image = self.image(index, srgb)?;
+        Ok(MaterialTexture::new(self.device, image, self.samplers, SamplerKey::default()))
     }
 
-    bytes
+    /// 1x1 texture for a material that doesn't have a map.
+    fn color(&mut self, color: [u8; 4], srgb: bool) -> Result<MaterialTexture, LoadError> {
+        let texture = match self.color_textures.get(&(color, srgb)) {
+            Some(texture) => texture.clone(),
+            None => {
+                let format = if srgb {
+                    wgpu::TextureFormat::Rgba8UnormSrgb
+                } else {
+                    wgpu::TextureFormat::Rgba8Unorm
+                };
+                let texture = Texture::from_color(
+                    self.device,
+                    self.queue,
+                    color,
+                    format,
+                    Some("Default Texture"),
+                )
+                    .map_err(LoadError::Texture)?;
+                let texture = Arc::new(texture);
+                self.color_textures.insert((color, srgb), texture.clone());
+                texture
+            }
+        };
+        Ok(MaterialTexture::new(self.device, texture, self.samplers, SamplerKey::default()))
+    }
+
+    /// Image `index`, decoded images are uploaded as mipmapped RGBA8.
+    fn image(&mut self, index: usize, srgb: bool) -> Result<Arc<Texture>, LoadError> {
+        if let Some(texture) = self.image_textures.get(&(index, srgb)) {
+            return Ok(texture.clone());
+        }
+
+        let texture = match self.images.get(index).ok_or(LoadError::MissingImage(index))? {
+            GltfImage::Pixels(data) => {
+                let pixels = gltf_image_to_rgba8(data).ok_or(LoadError::InvalidImage(index))?;
+                let format = if srgb {
+                    wgpu::TextureFormat::Rgba8UnormSrgb
+                } else {
+                    wgpu::TextureFormat::Rgba8Unorm
+                };
+                Texture::from_pixels(
+                    self.device,
+                    self.queue,
+                    &pixels,
+                    (data.width, data.height),
+                    4,
+                    format,
+                    Some(self.mipmaps),
+                    Some(self.label),
+                )
+            }
+            GltfImage::Ktx2(bytes) => {
+                let image = decode_ktx2(bytes, self.device.features(), srgb)?;
+                Texture::from_ktx2(
+                    self.device,
+                    self.queue,
+                    &image,
+                    Some(self.mipmaps),
+                    Some(self.label),
+                )
+            }
+        }
+            .map_err(LoadError::Texture)?;
+
+        let texture = Arc::new(texture);
+        self.image_textures.insert((index, srgb), texture.clone());
+        Ok(texture)
+    }
+}
+
+/// Maps a glTF sampler to wgpu, filters the glTF sampler leaves out keep the
+/// `SamplerKey` defaults.
+fn gltf_sampler_key(sampler: &gltf::texture::Sampler) -> SamplerKey {
+    use gltf::texture::{MagFilter, MinFilter};
+    let default = SamplerKey::default();
+
+    let mag_filter = match sampler.mag_filter() {
+        Some(MagFilter::Nearest) => wgpu::FilterMode::Nearest,
+        Some(MagFilter::Linear) => wgpu::FilterMode::Linear,
+        None => default.mag_filter,
+    };
+    let (min_filter, mipmap_filter, mipmaps) = match sampler.min_filter() {
+        // no mipmapping asked for, only the base level is sampled
+        Some(MinFilter::Nearest) => (wgpu::FilterMode::Nearest, wgpu::FilterMode::Nearest, false),
+        Some(MinFilter::Linear) => (wgpu::FilterMode::Linear, wgpu::FilterMode::Nearest, false),
+        Some(MinFilter::NearestMipmapNearest) => (wgpu::FilterMode::Nearest, wgpu::FilterMode::Nearest, true),
+        Some(MinFilter::LinearMipmapNearest) => (wgpu::FilterMode::Linear, wgpu::FilterMode::Nearest, true),
+        Some(MinFilter::NearestMipmapLinear) => (wgpu::FilterMode::Nearest, wgpu::FilterMode::Linear, true),
+        Some(MinFilter::LinearMipmapLinear) => (wgpu::FilterMode::Linear, wgpu::FilterMode::Linear, true),
+        None => (default.min_filter, default.mipmap_filter, default.mipmaps),
+    };
+
+    SamplerKey {
+        address_mode_u: gltf_address_mode(sampler.wrap_s()),
+        address_mode_v: gltf_address_mode(sampler.wrap_t()),
+        mag_filter,
+        min_filter,
+        mipmap_filter,
+        mipmaps,
+    }
+}
+
+fn gltf_address_mode(mode: gltf::texture::WrappingMode) -> wgpu::AddressMode {
+    match mode {
+        gltf::texture::WrappingMode::ClampToEdge => wgpu::AddressMode::ClampToEdge,
+        gltf::texture::WrappingMode::MirroredRepeat => wgpu::AddressMode::MirrorRepeat,
+        gltf::texture::WrappingMode::Repeat => wgpu::AddressMode::Repeat,
+    }
+}
+
+/// Converts any decoded glTF image to RGBA8, returns `None` if the pixels don't
+/// match the size.
+fn gltf_image_to_rgba8(data: &gltf::image::Data) -> Option<Vec<u8>> {
+    use gltf::image::Format;
+    use image::{DynamicImage, ImageBuffer};
+
+    let (width, height) = (data.width, data.height);
+    let pixels = data.pixels.clone();
+    let pixels_16 = || data.pixels.chunks_exact(2).map(|s| u16::from_ne_bytes([s[0], s[1]])).collect::<Vec<_>>();
+    let pixels_32 = || data.pixels.chunks_exact(4).map(|s| f32::from_ne_bytes([s[0], s[1], s[2], s[3]])).collect::<Vec<_>>();
+
+    let image = match data.format {
+        Format::R8 => DynamicImage::ImageLuma8(ImageBuffer::from_raw(width, height, pixels)?),
+        Format::R8G8 => DynamicImage::ImageLumaA8(ImageBuffer::from_raw(width, height, pixels)?),
+        Format::R8G8B8 => DynamicImage::ImageRgb8(ImageBuffer::from_raw(width, height, pixels)?),
+        Format::R8G8B8A8 => DynamicImage::ImageRgba8(ImageBuffer::from_raw(width, height, pixels)?),
+        Format::R16 => DynamicImage::ImageLuma16(ImageBuffer::from_raw(width, height, pixels_16())?),
+        Format::R16G16 => DynamicImage::ImageLumaA16(ImageBuffer::from_raw(width, height, pixels_16())?),
+        Format::R16G16B16 => DynamicImage::ImageRgb16(ImageBuffer::from_raw(width, height, pixels_16())?),
+        Format::R16G16B16A16 => DynamicImage::ImageRgba16(ImageBuffer::from_raw(width, height, pixels_16())?),
+        Format::R32G32B32FLOAT => DynamicImage::ImageRgb32F(ImageBuffer::from_raw(width, height, pixels_32())?),
+        Format::R32G32B32A32FLOAT => DynamicImage::ImageRgba32F(ImageBuffer::from_raw(width, height, pixels_32())?),
+    };
+
+    Some(image.into_rgba8().into_raw())
 }
@@ -0,0 +1,144 @@
+use cgmath::Rotation3;
+use serde::Deserialize;
+
+use super::instance::Instance;
+use super::resources::AssetSource;
+
+/// Everything `State` needs to build a scene, loaded from a RON file.
+/// See `res/scenes` for examples.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Scene {
+    pub camera: CameraDesc,
+    #[serde(default)]
+    pub models: Vec<ModelDesc>,
+    /// Only the first light is used for now.
+    #[serde(default)]
+    pub lights: Vec<LightDesc>,
+    #[serde(default)]
+    pub fog_volumes: Vec<Transform>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CameraDesc {
+    pub position: [f32; 3],
+    #[serde(default)]
+    pub yaw: f32,
+    #[serde(default)]
+    pub pitch: f32,
+    #[serde(default = "default_fovy")]
+    pub fovy: f32,
+    #[serde(default = "default_speed")]
+    pub speed: f32,
+    #[serde(default = "default_sensitivity")]
+    pub sensitivity: f32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ModelDesc {
+    pub path: String,
+    #[serde(default = "default_instances")]
+    pub instances: Vec<Transform>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LightDesc {
+    #[serde(default)]
+    pub position: [f32; 3],
+    #[serde(default = "default_color")]
+    pub color: [f32; 3],
+    pub intensity: f32,
+    /// Move the light around and cycle its color over time.
+    #[serde(default)]
+    pub animated: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Transform {
+    #[serde(default)]
+    pub position: [f32; 3],
+    /// Euler angles in degrees, applied in XYZ order.
+    #[serde(default)]
+    pub rotation: [f32; 3],
+    #[serde(default = "default_scale")]
+    pub scale: [f32; 3],
+}
+
+impl Scene {
+    pub async fn load(source: &AssetSource, file_name: &str) -> anyhow::Result<Self> {
+        log::info!("scene: Loading file {}", file_name);
+        let text = source.load_string(file_name).await?;
+        Ok(ron::from_str(&text)?)
+    }
+
+    /// A scene with just one instance of `model_path`, viewed from the side.
+    pub fn from_model(model_path: &str) -> Self {
+        Self {
+            camera: CameraDesc {
+                position: [-10.0, 2.0, 0.0],
+                yaw: 0.0,
+                pitch: 0.0,
+                fovy: default_fovy(),
+                speed: default_speed(),
+                sensitivity: default_sensitivity(),
+            },
+            models: vec![ModelDesc {
+                path: model_path.to_string(),
+                instances: default_instances(),
+            }],
+            lights: vec![LightDesc {
+                position: [-5.0, 10.0, 5.0],
+                color: default_color(),
+                intensity: 1000.0,
+                animated: false,
+            }],
+            fog_volumes: Vec::new(),
+        }
+    }
+}
+
+impl Transform {
+    pub fn to_instance(&self) -> Instance {
+        let rotation = cgmath::Quaternion::from_angle_z(cgmath::Deg(self.rotation[2]))
+            * cgmath::Quaternion::from_angle_y(cgmath::Deg(self.rotation[1]))
+            * cgmath::Quaternion::from_angle_x(cgmath::Deg(self.rotation[0]));
+        Instance {
+            position: self.position.into(),
+            rotation,
+            scale: self.scale.into(),
+        }
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            position: [0.0; 3],
+            rotation: [0.0; 3],
+            scale: default_scale(),
+        }
+    }
+}
+
+fn default_fovy() -> f32 {
+    55.0
+}
+
+fn default_speed() -> f32 {
+    400.0
+}
+
+fn default_sensitivity() -> f32 {
+    2.0
+}
+
+fn default_instances() -> Vec<Transform> {
+    vec![Transform::default()]
+}
+
+fn default_color() -> [f32; 3] {
+    [1.0; 3]
+}
+
+fn default_scale() -> [f32; 3] {
+    [1.0; 3]
+}
@@ -0,0 +1,110 @@
+use super::resources::AssetSource;
+
+/// Runtime options for the renderer. The native binary fills these in from
+/// the command line, the web version always uses the defaults.
+#[derive(Debug, Clone)]
+pub struct Settings {
+    /// Where `scene` & `model`, and the models in the scene, are loaded from.
+    pub assets: AssetSource,
+    /// Scene file to load.
+    pub scene: String,
+    /// View a single model instead of loading `scene`.
+    pub model: Option<String>,
+    /// Initial window size, uses the platform default if `None`.
+    pub window_size: Option<(u32, u32)>,
+    /// Borderless fullscreen instead of a window.
+    pub fullscreen: bool,
+    /// Falls back to `Fifo` if the surface doesn't support it.
+    pub present_mode: wgpu::PresentMode,
+    pub backends: wgpu::Backends,
+    pub shader: ShaderSettings,
+    /// Anisotropic filtering level of the material samplers, 1 to 16.
+    pub anisotropy: u16,
+    pub fog: bool,
+    pub shadows: bool,
+    /// Draw the cube showing the light position.
+    pub light_debug: bool,
+    /// Watch `res/shaders` in the source tree and rebuild the pipelines when a
+    /// shader changes. Native only.
+    pub hot_reload_shaders: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            assets: AssetSource::Embedded,
+            scene: "scenes/sponza.ron".to_string(),
+            model: None,
+            window_size: None,
+            fullscreen: true,
+            present_mode: wgpu::PresentMode::Fifo,
+            backends: wgpu::Backends::PRIMARY | wgpu::Backends::GL,
+            shader: ShaderSettings::default(),
+            anisotropy: 16,
+            fog: true,
+            shadows: true,
+            light_debug: true,
+            hot_reload_shaders: false,
+        }
+    }
+}
+
+/// Tuning values shared by the renderer & the shaders. They're passed to every
+/// pass as preprocessor defines, so the shaders don't hardcode them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShaderSettings {
+    /// Width & height of each shadow map layer.
+    pub shadow_map_size: u32,
+    /// Shadow map texels sampled in every direction for filtering.
+    pub shadow_samples: u32,
+    /// Ray march steps through a fog volume.
+    pub fog_max_steps: u32,
+    pub fog_step_size: f32,
+    /// Ray march steps from a fog sample towards the light.
+    pub fog_light_max_steps: u32,
+    pub fog_light_step_size: f32,
+    pub fog_density: f32,
+    pub fog_light_density: f32,
+    pub fog_alpha: f32,
+}
+
+impl ShaderSettings {
+    /// The defines, named like the constants the shaders use.
+    pub fn defines(&self) -> Vec<(&'static str, String)> {
+        // -n..=n texels on both axes, (2n + 1)^2 samples
+        let shadow_sample_count = (2 * self.shadow_samples + 1).pow(2);
+        vec![
+            ("SHADOW_MAP_SIZE", self.shadow_map_size.max(1).to_string()),
+            ("SHADOW_SAMPLES", self.shadow_samples.to_string()),
+            ("INV_SHADOW_SAMPLES", float(1.0 / shadow_sample_count as f32)),
+            ("FOG_MAX_STEPS", self.fog_max_steps.to_string()),
+            ("FOG_STEP_SIZE", float(self.fog_step_size)),
+            ("FOG_LIGHT_MAX_STEPS", self.fog_light_max_steps.to_string()),
+            ("FOG_LIGHT_STEP_SIZE", float(self.fog_light_step_size)),
+            ("FOG_DENSITY", float(self.fog_density)),
+            ("FOG_LIGHT_DENSITY", float(self.fog_light_density)),
+            ("FOG_ALPHA", float(self.fog_alpha)),
+        ]
+    }
+}
+
+impl Default for ShaderSettings {
+    fn default() -> Self {
+        Self {
+            shadow_map_size: 2048,
+            shadow_samples: 2,
+            fog_max_steps: 30,
+            fog_step_size: 5.0,
+            fog_light_max_steps: 10,
+            fog_light_step_size: 10.0,
+            fog_density: 2.0,
+            fog_light_density: 3.0,
+            fog_alpha: 1.0,
+        }
+    }
+}
+
+/// A WGSL float literal, `Debug` keeps the `.0` of whole numbers.
+fn float(value: f32) -> String {
+    format!("{:?}", value)
+}
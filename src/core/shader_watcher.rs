@@ -0,0 +1,70 @@
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+
+use notify::{EventKind, RecursiveMode, Watcher};
+
+use crate::core::resources::AssetSource;
+use crate::shaders::preprocessor::{preprocess_wgsl_with, PreprocessError, PreprocessedShader};
+
+/// Watches the shaders of the repository on disk, for reloading them while
+/// developing instead of using the embedded ones.
+pub struct ShaderWatcher {
+    /// `res`, which the shader paths are relative to.
+    assets: AssetSource,
+    shader_dir: PathBuf,
+    events: mpsc::Receiver<notify::Result<notify::Event>>,
+    // stops watching when dropped
+    _watcher: notify::RecommendedWatcher,
+}
+
+impl ShaderWatcher {
+    /// Watches `res/shaders` of the source tree the renderer was built from.
+    pub fn new() -> notify::Result<Self> {
+        let res_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("res");
+        let shader_dir = res_dir.join("shaders");
+
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+        watcher.watch(&shader_dir, RecursiveMode::Recursive)?;
+        println!("shader watcher: watching {}", shader_dir.display());
+
+        Ok(Self {
+            assets: AssetSource::Directory(res_dir),
+            shader_dir,
+            events,
+            _watcher: watcher,
+        })
+    }
+
+    /// Files created or modified since the last call, relative to `shaders/`
+    /// like the names `RenderPass` uses.
+    pub fn changed_files(&self) -> Vec<String> {
+        let mut files = Vec::new();
+        for event in self.events.try_iter() {
+            let event = match event {
+                Ok(event) => event,
+                Err(e) => {
+                    println!("shader watcher: {}", e);
+                    continue;
+                }
+            };
+            if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                continue;
+            }
+            for path in event.paths {
+                if let Ok(relative) = path.strip_prefix(&self.shader_dir) {
+                    let file = relative.to_string_lossy().replace('\\', "/");
+                    if !files.contains(&file) {
+                        files.push(file);
+                    }
+                }
+            }
+        }
+        files
+    }
+
+    /// Preprocesses `filename` with the includes from disk.
+    pub fn preprocess(&self, filename: &str, defines: &[(&str, &str)]) -> Result<PreprocessedShader, PreprocessError> {
+        preprocess_wgsl_with(filename, defines, |path| pollster::block_on(self.assets.load_string(path)))
+    }
+}
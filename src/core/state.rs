@@ -1,21 +1,30 @@
-use cgmath::prelude::*;
 use std::default::Default;
-use std::mem;
 use std::time::Duration;
 
+use anyhow::Context;
 use wgpu::util::DeviceExt;
 use winit::{event::*, window::Window};
-use crate::core::material::MaterialUniform;
+use cgmath::{EuclideanSpace, InnerSpace, Transform};
+use crate::core::material::AlphaMode;
+use crate::shaders::structs::{wgsl_struct, Usage};
 
 use super::camera::{Camera, CameraController, CameraUniform};
+#[cfg(not(target_arch = "wasm32"))]
+use super::capture::Capture;
 use super::instance::{Instance, InstanceRaw};
+use super::layouts::{self, Group};
 use super::light::{DrawLight, LightUniform};
+use super::mipmap::MipmapGenerator;
+use super::mesh::Mesh;
 use super::model::{DrawModel, Model, ModelVertex, Vertex};
 use super::pass::RenderPass;
 use super::resources;
-use super::texture::Texture;
+use super::scene::Scene;
+use super::settings::Settings;
+#[cfg(not(target_arch = "wasm32"))]
+use super::shader_watcher::ShaderWatcher;
+use super::texture::{SamplerCache, Texture};
 
-const SHADOW_MAP_SIZE: u32 = 2048;
 const SHADOW_MAP_LAYERS: u32 = 6;
 
 #[repr(C)]
@@ -23,18 +32,60 @@ const SHADOW_MAP_LAYERS: u32 = 6;
 pub struct GlobalUniforms {
     pub time: f32,
     pub light_matrix_index: u32,
-    pub use_shadowmaps: u32,
-    pub _padding: u32,
+    pub _padding: [u32; 2],
+}
+
+wgsl_struct!(GlobalUniforms, Usage::Uniform, [time, light_matrix_index, _padding]);
+
+/// Where the final color output of a frame is written to.
+pub enum RenderTarget {
+    /// Presented to a window.
+    Surface(wgpu::Surface),
+    /// Rendered into a texture, for headless use.
+    Offscreen(Texture),
+}
+
+/// A model and the instances it's drawn with.
+struct InstancedModel {
+    model: Model,
+    instances: Vec<Instance>,
+    instance_buffer: wgpu::Buffer,
+}
+
+/// The same pass for single & double sided materials, which only differ in culling.
+struct CulledPasses {
+    single_sided: RenderPass,
+    double_sided: RenderPass,
+}
+
+impl CulledPasses {
+    fn new(label: &str, create: impl Fn(&str, Option<wgpu::Face>) -> RenderPass) -> Self {
+        Self {
+            single_sided: create(label, Some(wgpu::Face::Back)),
+            double_sided: create(&format!("{} double sided", label), None),
+        }
+    }
+
+    fn get(&self, double_sided: bool) -> &RenderPass {
+        if double_sided { &self.double_sided } else { &self.single_sided }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn iter_mut(&mut self) -> impl Iterator<Item = &mut RenderPass> {
+        [&mut self.single_sided, &mut self.double_sided].into_iter()
+    }
 }
 
 pub struct State {
     pub size: winit::dpi::PhysicalSize<u32>,
 
-    surface: wgpu::Surface,
+    target: RenderTarget,
     device: wgpu::Device,
     queue: wgpu::Queue,
     config: wgpu::SurfaceConfiguration,
-    geometry_pass: RenderPass,
+    geometry_pass: CulledPasses,
+    geometry_mask_pass: CulledPasses,
+    geometry_blend_pass: CulledPasses,
     #[cfg(not(target_arch = "wasm32"))]
     fog_pass: RenderPass,
     camera: Camera,
@@ -42,37 +93,46 @@ pub struct State {
     camera_buffer: wgpu::Buffer,
     global_bind_group: wgpu::BindGroup,
     camera_controller: CameraController,
-    geom_instances: Vec<Instance>,
-    geom_instance_buffer: wgpu::Buffer,
+    geom_models: Vec<InstancedModel>,
+    texture_bind_group_layout: wgpu::BindGroupLayout,
+    sampler_cache: SamplerCache,
     #[cfg(not(target_arch = "wasm32"))]
     fog_instances: Vec<Instance>,
     #[cfg(not(target_arch = "wasm32"))]
     fog_instance_buffer: wgpu::Buffer,
     geometry_depth_texture: Texture,
-    geom_model: Model,
     #[cfg(not(target_arch = "wasm32"))]
     fog_model: Model,
     light_model: Model,
     light_uniform: LightUniform,
+    light_animated: bool,
     light_buffer: wgpu::Buffer,
     light_debug_pass: RenderPass,
     light_depth_bind_group: wgpu::BindGroup,
     geometry_depth_bind_group: wgpu::BindGroup,
     geometry_depth_bind_group_layout: wgpu::BindGroupLayout,
-    light_depth_pass: RenderPass,
+    light_depth_pass: CulledPasses,
+    light_depth_mask_pass: CulledPasses,
     light_depth_texture_target_views: [wgpu::TextureView; SHADOW_MAP_LAYERS as usize],
     global_uniforms: GlobalUniforms,
     global_uniforms_buffer: wgpu::Buffer,
+    #[cfg(not(target_arch = "wasm32"))]
+    screenshot_requested: bool,
+    #[cfg(not(target_arch = "wasm32"))]
+    screenshot: Option<anyhow::Result<Capture>>,
+    #[cfg(not(target_arch = "wasm32"))]
+    shader_watcher: Option<ShaderWatcher>,
+    settings: Settings,
 }
 
 impl State {
-    pub async fn new(window: &Window) -> Self {
+    pub async fn new(window: &Window, scene: &Scene, settings: &Settings) -> anyhow::Result<Self> {
         log::info!("Creating surface");
         let mut size = window.inner_size();
         size.width = size.width.max(1);
         size.height = size.height.max(1);
-        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor { backends: wgpu::Backends::PRIMARY | wgpu::Backends::GL, ..Default::default() });
-        let surface = unsafe { instance.create_surface(window) }.unwrap();
+        let instance = State::create_instance(settings.backends);
+        let surface = unsafe { instance.create_surface(window) }.context("failed to create surface")?;
 
         let adapter = instance
             .request_adapter(&wgpu::RequestAdapterOptions {
@@ -81,12 +141,100 @@ impl State {
                 force_fallback_adapter: false,
             })
             .await
-            .expect("failed to get adapter");
+            .context("failed to get adapter")?;
+
+        let (device, queue) = State::request_device(&adapter)
+            .await
+            .context("failed to get device")?;
+
+        let caps = surface.get_capabilities(&adapter);
+        let present_mode = if caps.present_modes.contains(&settings.present_mode) {
+            settings.present_mode
+        } else {
+            log::warn!("Present mode {:?} not supported, using Fifo", settings.present_mode);
+            wgpu::PresentMode::Fifo
+        };
+        let config = wgpu::SurfaceConfiguration {
+            // COPY_SRC for screenshots, if supported
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | (caps.usages & wgpu::TextureUsages::COPY_SRC),
+            format: caps.formats[0],
+            width: size.width,
+            height: size.height,
+            present_mode,
+            alpha_mode: wgpu::CompositeAlphaMode::Opaque,
+            view_formats: vec![caps.formats[0]],
+        };
 
-        let (device, queue) = adapter
+        surface.configure(&device, &config);
+
+        State::with_target(RenderTarget::Surface(surface), device, queue, config, scene, settings).await
+    }
+
+    /// Creates a state without a window that renders `scene` into an offscreen
+    /// texture of the given size and format. Falls back to a software adapter if no
+    /// hardware adapter is available.
+    pub async fn new_headless(
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        scene: &Scene,
+        settings: &Settings,
+    ) -> anyhow::Result<Self> {
+        log::info!("Creating offscreen target");
+        let instance = State::create_instance(settings.backends);
+
+        let mut adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                compatible_surface: None,
+                force_fallback_adapter: false,
+            })
+            .await;
+        if adapter.is_none() {
+            log::info!("No hardware adapter, trying fallback adapter");
+            adapter = instance
+                .request_adapter(&wgpu::RequestAdapterOptions {
+                    power_preference: wgpu::PowerPreference::HighPerformance,
+                    compatible_surface: None,
+                    force_fallback_adapter: true,
+                })
+                .await;
+        }
+        let adapter = adapter.context("failed to get adapter")?;
+        log::info!("Using adapter {:?}", adapter.get_info());
+
+        let (device, queue) = State::request_device(&adapter)
+            .await
+            .context("failed to get device")?;
+
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            format,
+            width: width.max(1),
+            height: height.max(1),
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Opaque,
+            view_formats: vec![format],
+        };
+
+        let color_texture = State::create_offscreen_texture(&device, &config);
+
+        State::with_target(RenderTarget::Offscreen(color_texture), device, queue, config, scene, settings).await
+    }
+
+    fn create_instance(backends: wgpu::Backends) -> wgpu::Instance {
+        wgpu::Instance::new(wgpu::InstanceDescriptor { backends, ..Default::default() })
+    }
+
+    async fn request_device(adapter: &wgpu::Adapter) -> Result<(wgpu::Device, wgpu::Queue), wgpu::RequestDeviceError> {
+        // for KTX2 textures, whichever the adapter has
+        let compression = wgpu::Features::TEXTURE_COMPRESSION_BC
+            | wgpu::Features::TEXTURE_COMPRESSION_ETC2
+            | wgpu::Features::TEXTURE_COMPRESSION_ASTC;
+        adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
-                    features: wgpu::Features::default(),
+                    features: adapter.features() & compression,
                     limits: if cfg!(target_arch = "wasm32") {
                         // TODO: remove once webgpu?
                         wgpu::Limits::downlevel_webgl2_defaults()
@@ -98,26 +246,23 @@ impl State {
                 None,
             )
             .await
-            .expect("failed to get device");
-
-        let caps = surface.get_capabilities(&adapter);
-        let config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: caps.formats[0],
-            width: size.width,
-            height: size.height,
-            present_mode: wgpu::PresentMode::Fifo,
-            alpha_mode: wgpu::CompositeAlphaMode::Opaque,
-            view_formats: vec![caps.formats[0]],
-        };
+    }
 
-        surface.configure(&device, &config);
+    async fn with_target(
+        target: RenderTarget,
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        config: wgpu::SurfaceConfiguration,
+        scene: &Scene,
+        settings: &Settings,
+    ) -> anyhow::Result<Self> {
+        let size = winit::dpi::PhysicalSize::new(config.width, config.height);
 
         let camera = Camera::new(
-            (-500.0, 150.0, 0.0).into(),
-            0.0,
-            0.0,
-            55.0,
+            scene.camera.position.into(),
+            scene.camera.pitch,
+            scene.camera.yaw,
+            scene.camera.fovy,
             config.width as f32 / config.height as f32,
         );
 
@@ -129,15 +274,22 @@ impl State {
             contents: bytemuck::cast_slice(&[camera_uniform]),
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
-        let camera_uniform_size = mem::size_of::<CameraUniform>() as u64;
 
-        let light_uniform = LightUniform::new([0.0, 0.0, 0.0], [1.0, 1.0, 1.0, 250000.0]);
+        if scene.lights.len() > 1 {
+            log::warn!("Scene has {} lights, only the first one is used", scene.lights.len());
+        }
+        let (light_uniform, light_animated) = match scene.lights.first() {
+            Some(light) => (
+                LightUniform::new(light.position, [light.color[0], light.color[1], light.color[2], light.intensity]),
+                light.animated,
+            ),
+            None => (LightUniform::new([0.0, 0.0, 0.0], [0.0, 0.0, 0.0, 0.0]), false),
+        };
         let light_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Light UB"),
             contents: bytemuck::cast_slice(&[light_uniform]),
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
-        let light_uniform_size = mem::size_of::<LightUniform>() as u64;
 
         let global_uniforms = GlobalUniforms::default();
         let global_uniforms_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -145,47 +297,11 @@ impl State {
             contents: bytemuck::cast_slice(&[global_uniforms]),
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
-        let global_uniform_size = mem::size_of::<GlobalUniforms>() as u64;
-
-        let global_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[
-                    // CameraUniform
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: wgpu::BufferSize::new(camera_uniform_size),
-                        },
-                        count: None,
-                    },
-                    // LightUniform
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: wgpu::BufferSize::new(light_uniform_size),
-                        },
-                        count: None,
-                    },
-                    // global_uniforms
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 2,
-                        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: wgpu::BufferSize::new(global_uniform_size),
-                        },
-                        count: None,
-                    },
-                ],
-                label: Some("camera_bind_group_layout"),
-            });
+
+        let global_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &layouts::global_entries(),
+            label: Some("camera_bind_group_layout"),
+        });
         let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout: &global_bind_group_layout,
             entries: &[
@@ -208,20 +324,21 @@ impl State {
             label: Some("camera_bind_group"),
         });
 
-        let camera_controller = CameraController::new(400.0, 2.0);
+        let camera_controller = CameraController::new(scene.camera.speed, scene.camera.sensitivity);
 
         let geometry_depth_texture = State::create_geometry_depth_texture(&device, &config);
 
+        // still needed for the bind group when shadows are off
+        let shadow_map_size = if settings.shadows { settings.shader.shadow_map_size.max(1) } else { 1 };
         let light_depth_texture = Texture::create_depth_texture(
             &device,
             "light_depth_texture",
-            Some(wgpu::CompareFunction::LessEqual),
-            SHADOW_MAP_SIZE,
-            SHADOW_MAP_SIZE,
+            shadow_map_size,
+            shadow_map_size,
             SHADOW_MAP_LAYERS,
             wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
-            true,
         );
+        let light_depth_sampler = Texture::create_sampler(&device, Some(wgpu::CompareFunction::LessEqual), true);
 
         let light_depth_texture_target_views = (0..SHADOW_MAP_LAYERS)
             .map(|i| {
@@ -240,29 +357,10 @@ impl State {
             .try_into()
             .expect("failed to create light depth texture views");
 
-        let light_depth_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[
-                    // light cubemap
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            view_dimension: wgpu::TextureViewDimension::D2Array,
-                            sample_type: wgpu::TextureSampleType::Depth,
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
-                        count: None,
-                    },
-                ],
-                label: Some("Light Bind Group Layout"),
-            });
+        let light_depth_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &layouts::light_depth_entries(),
+            label: Some("Light Bind Group Layout"),
+        });
 
         let light_depth_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout: &light_depth_bind_group_layout,
@@ -274,155 +372,86 @@ impl State {
                 },
                 wgpu::BindGroupEntry {
                     binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&light_depth_texture.sampler),
+                    resource: wgpu::BindingResource::Sampler(&light_depth_sampler),
                 },
             ],
             label: Some("Light Bind Group"),
         });
 
-        let geometry_depth_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[
-                    // geometry depth
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            sample_type: wgpu::TextureSampleType::Depth,
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                ],
-                label: Some("Depth Bind Group Layout"),
-            });
+        let geometry_depth_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &layouts::geometry_depth_entries(),
+            label: Some("Depth Bind Group Layout"),
+        });
 
         let geometry_depth_bind_group = State::create_geometry_depth_bind_group(&device, &geometry_depth_bind_group_layout, &geometry_depth_texture);
 
-        let material_uniform_size = mem::size_of::<MaterialUniform>() as u64;
-        let texture_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[
-                    // diffuse
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                    // normal
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 2,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 3,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                    // metallic + roughness
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 4,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 5,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                    // material uniform
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 6,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: wgpu::BufferSize::new(material_uniform_size),
-                        },
-                        count: None,
-                    },
-                ],
-                label: Some("texture_bind_group_layout"),
-            });
+        let texture_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &layouts::texture_entries(),
+            label: Some("texture_bind_group_layout"),
+        });
 
-        let geom_model = resources::load_model_gltf(
-            "models/Sponza.glb",
-            &device,
-            &queue,
-            &texture_bind_group_layout,
-        )
-            .await
-            .unwrap();
+        let group_layout = |group: Group| match group {
+            Group::Global => &global_bind_group_layout,
+            Group::LightDepth => &light_depth_bind_group_layout,
+            Group::GeometryDepth => &geometry_depth_bind_group_layout,
+            Group::Texture => &texture_bind_group_layout,
+            Group::Mipmap => unreachable!("the mipmap layout belongs to MipmapGenerator"),
+        };
+
+        let mut sampler_cache = SamplerCache::new(settings.anisotropy);
+        let mut mipmaps = MipmapGenerator::new(&device);
+        let mut geom_models = Vec::new();
+        for model_desc in &scene.models {
+            let model = resources::load_model(
+                &settings.assets,
+                &model_desc.path,
+                &device,
+                &queue,
+                &texture_bind_group_layout,
+                &mut sampler_cache,
+                &mut mipmaps,
+            )
+                .await
+                .with_context(|| format!("failed to load model {}", model_desc.path))?;
+            let instances = model_desc.instances.iter().map(|t| t.to_instance()).collect::<Vec<_>>();
+            let instance_data = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
+            let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Geometry Instance Buffer"),
+                contents: bytemuck::cast_slice(&instance_data),
+                usage: wgpu::BufferUsages::VERTEX,
+            });
+            geom_models.push(InstancedModel {
+                model,
+                instances,
+                instance_buffer,
+            });
+        }
 
         #[cfg(not(target_arch = "wasm32"))]
         let fog_model = resources::load_model_gltf(
+            &resources::AssetSource::Embedded,
             "models/Cube.glb",
             &device,
             &queue,
             &texture_bind_group_layout,
+            &mut sampler_cache,
+            &mut mipmaps,
         )
-            .await
-            .unwrap();
+            .await?;
 
         let light_model = resources::load_model_gltf(
+            &resources::AssetSource::Embedded,
             "models/Cube.glb",
             &device,
             &queue,
             &texture_bind_group_layout,
+            &mut sampler_cache,
+            &mut mipmaps,
         )
-            .await
-            .unwrap();
-
-        let geom_instances = vec![Instance {
-            // this sponza model isn't quite centered
-            position: [60.0, 0.0, 35.0].into(),
-            rotation: cgmath::Quaternion::one(),
-            scale: [1.0, 1.0, 1.0].into(),
-        }];
-        let geom_instance_data = geom_instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
-        let geom_instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Geometry Instance Buffer"),
-            contents: bytemuck::cast_slice(&geom_instance_data),
-            usage: wgpu::BufferUsages::VERTEX,
-        });
+            .await?;
 
         #[cfg(not(target_arch = "wasm32"))]
-        let fog_instances = vec![Instance {
-            position: [0.0, 30.0, 0.0].into(),
-            rotation: cgmath::Quaternion::one(),
-            scale: [1360.0, 30.0, 600.0].into(),
-        }];
+        let fog_instances = scene.fog_volumes.iter().map(|t| t.to_instance()).collect::<Vec<_>>();
         #[cfg(not(target_arch = "wasm32"))]
         let fog_instance_data = fog_instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
         #[cfg(not(target_arch = "wasm32"))]
@@ -432,47 +461,89 @@ impl State {
             usage: wgpu::BufferUsages::VERTEX,
         });
 
-        let light_depth_pass = RenderPass::new(
-            &device,
-            &[
-                &global_bind_group_layout,
-            ],
-            &[],
-            "depth.wgsl",
-            None,
-            Some(Texture::DEPTH_FORMAT),
-            &[ModelVertex::desc(), InstanceRaw::desc()],
-            "light depth pass",
-            true,
-            false,
-            true,
-            Some(wgpu::Face::Back),
-        );
+        // tuning values, the same for every pass
+        let shader_settings = settings.shader.defines();
+        let constant_defines = shader_settings
+            .iter()
+            .map(|(name, value)| (*name, value.as_str()))
+            .collect::<Vec<_>>();
+        let constant_mask_defines = [constant_defines.as_slice(), &[("ALPHA_MASK", "")]].concat();
 
-        let geometry_pass = RenderPass::new(
-            &device,
-            &[
-                &global_bind_group_layout,
-                &light_depth_bind_group_layout,
-                &texture_bind_group_layout,
-            ],
-            &[],
-            "pbr.wgsl",
-            Some(config.format),
-            Some(Texture::DEPTH_FORMAT),
-            &[ModelVertex::desc(), InstanceRaw::desc()],
-            "geometry pass",
-            false,
-            true,
-            true,
-            Some(wgpu::Face::Back),
-        );
+        // shader variants, instead of checking these at runtime
+        let mut lit_defines = constant_defines.clone();
+        if settings.shadows {
+            lit_defines.push(("SHADOWS", ""));
+        }
+        if cfg!(target_arch = "wasm32") {
+            lit_defines.push(("WEBGL", ""));
+        }
+        let lit_mask_defines = [lit_defines.as_slice(), &[("ALPHA_MASK", "")]].concat();
+        let lit_blend_defines = [lit_defines.as_slice(), &[("ALPHA_BLEND", "")]].concat();
+
+        let light_depth_passes = |label: &str, defines: &[(&str, &str)]| CulledPasses::new(label, |label, cull_mode| {
+            RenderPass::new(
+                &device,
+                &layouts::depth_groups().map(group_layout),
+                &[],
+                "depth.wgsl",
+                defines,
+                None,
+                Some(Texture::DEPTH_FORMAT),
+                &[ModelVertex::desc(), InstanceRaw::desc()],
+                label,
+                true,
+                false,
+                true,
+                cull_mode,
+            )
+        });
+        let light_depth_pass = light_depth_passes("light depth pass", &constant_defines);
+        let light_depth_mask_pass = light_depth_passes("light depth mask pass", &constant_mask_defines);
+
+        let geometry_passes = |label: &str, defines: &[(&str, &str)]| CulledPasses::new(label, |label, cull_mode| {
+            RenderPass::new(
+                &device,
+                &layouts::pbr_groups().map(group_layout),
+                &[],
+                "pbr.wgsl",
+                defines,
+                Some(config.format),
+                Some(Texture::DEPTH_FORMAT),
+                &[ModelVertex::desc(), InstanceRaw::desc()],
+                label,
+                false,
+                false,
+                true,
+                cull_mode,
+            )
+        });
+        let geometry_pass = geometry_passes("geometry pass", &lit_defines);
+        let geometry_mask_pass = geometry_passes("geometry mask pass", &lit_mask_defines);
+
+        let geometry_blend_pass = CulledPasses::new("geometry blend pass", |label, cull_mode| {
+            RenderPass::new(
+                &device,
+                &layouts::pbr_groups().map(group_layout),
+                &[],
+                "pbr.wgsl",
+                &lit_blend_defines,
+                Some(config.format),
+                Some(Texture::DEPTH_FORMAT),
+                &[ModelVertex::desc(), InstanceRaw::desc()],
+                label,
+                false,
+                true,
+                false,
+                cull_mode,
+            )
+        });
 
         let light_debug_pass = RenderPass::new(
             &device,
-            &[&global_bind_group_layout],
+            &layouts::light_debug_groups().map(group_layout),
             &[],
             "light_debug.wgsl",
+            &constant_defines,
             Some(config.format),
             Some(Texture::DEPTH_FORMAT),
             &[ModelVertex::desc()],
@@ -486,15 +557,13 @@ impl State {
         #[cfg(not(target_arch = "wasm32"))]
         let fog_pass = RenderPass::new(
             &device,
-            &[
-                &global_bind_group_layout,
-                &light_depth_bind_group_layout,
-                &geometry_depth_bind_group_layout,
-            ],
+            &layouts::fog_groups().map(group_layout),
             &[],
             "fog.wgsl",
+            &lit_defines,
             Some(config.format),
-            Some(Texture::DEPTH_FORMAT),
+            // no depth test, the shader compares against geometry depth itself
+            None,
             &[ModelVertex::desc(), InstanceRaw::desc()],
             "fog pass",
             false,
@@ -503,13 +572,24 @@ impl State {
             Some(wgpu::Face::Back),
         );
 
-        Self {
+        #[cfg(not(target_arch = "wasm32"))]
+        let shader_watcher = if settings.hot_reload_shaders {
+            ShaderWatcher::new()
+                .map_err(|e| println!("shader watcher: can't watch the shaders, hot reload disabled: {}", e))
+                .ok()
+        } else {
+            None
+        };
+
+        Ok(Self {
             size,
-            surface,
+            target,
             device,
             queue,
             config,
             geometry_pass,
+            geometry_mask_pass,
+            geometry_blend_pass,
             #[cfg(not(target_arch = "wasm32"))]
             fog_pass,
             camera,
@@ -517,28 +597,37 @@ impl State {
             camera_buffer,
             global_bind_group: camera_bind_group,
             camera_controller,
-            geom_instances,
-            geom_instance_buffer,
+            geom_models,
+            texture_bind_group_layout,
+            sampler_cache,
             #[cfg(not(target_arch = "wasm32"))]
             fog_instances,
             #[cfg(not(target_arch = "wasm32"))]
             fog_instance_buffer,
             geometry_depth_texture,
-            geom_model,
             #[cfg(not(target_arch = "wasm32"))]
             fog_model,
             light_model,
             light_uniform,
+            light_animated,
             light_buffer,
             light_debug_pass,
             light_depth_bind_group,
             geometry_depth_bind_group,
             geometry_depth_bind_group_layout,
             light_depth_pass,
+            light_depth_mask_pass,
             light_depth_texture_target_views,
             global_uniforms,
             global_uniforms_buffer,
-        }
+            #[cfg(not(target_arch = "wasm32"))]
+            screenshot_requested: false,
+            #[cfg(not(target_arch = "wasm32"))]
+            screenshot: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            shader_watcher,
+            settings: settings.clone(),
+        })
     }
 
     pub fn create_geometry_depth_bind_group(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, geometry_depth_texture: &Texture) -> wgpu::BindGroup {
@@ -550,34 +639,148 @@ impl State {
                     binding: 0,
                     resource: wgpu::BindingResource::TextureView(&geometry_depth_texture.view),
                 },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&geometry_depth_texture.sampler),
-                },
             ],
             label: Some("Depth Bind Group"),
         })
     }
 
+    fn create_offscreen_texture(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> Texture {
+        Texture::create_render_target(
+            device,
+            "offscreen_color_texture",
+            config.width,
+            config.height,
+            config.format,
+            config.usage,
+        )
+    }
+
     fn create_geometry_depth_texture(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> Texture {
         Texture::create_depth_texture(
             device,
             "geometry_depth_texture",
-            None,
             config.width,
             config.height,
             1,
-            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
-            true,
+            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_SRC,
+        )
+    }
+
+    pub fn set_camera(&mut self, position: cgmath::Point3<f32>, yaw: f32, pitch: f32) {
+        self.camera.position = position;
+        self.camera.yaw = yaw;
+        self.camera.pitch = pitch;
+    }
+
+    pub fn target(&self) -> &RenderTarget {
+        &self.target
+    }
+
+    /// Reads back the color target of the last rendered frame.
+    /// Window surfaces can't be read after presenting, use `request_screenshot` for those.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn capture_color(&self) -> anyhow::Result<Capture> {
+        match &self.target {
+            RenderTarget::Offscreen(texture) => Capture::read_texture(
+                &self.device,
+                &self.queue,
+                &texture.texture,
+                wgpu::TextureAspect::All,
+            ),
+            RenderTarget::Surface(_) => anyhow::bail!("capture_color: can't read back a window surface"),
+        }
+    }
+
+    /// Reads back `geometry_depth_texture` of the last rendered frame.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn capture_depth(&self) -> anyhow::Result<Capture> {
+        Capture::read_texture(
+            &self.device,
+            &self.queue,
+            &self.geometry_depth_texture.texture,
+            wgpu::TextureAspect::DepthOnly,
         )
     }
 
+    /// Captures the color target during the next `render`, see `take_screenshot`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn request_screenshot(&mut self) {
+        self.screenshot_requested = true;
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn take_screenshot(&mut self) -> Option<anyhow::Result<Capture>> {
+        self.screenshot.take()
+    }
+
+    pub fn anisotropy(&self) -> u16 {
+        self.sampler_cache.anisotropy()
+    }
+
+    /// Sets the anisotropic filtering level of the material samplers, 1 to 16.
+    pub fn set_anisotropy(&mut self, anisotropy: u16) {
+        self.sampler_cache.set_anisotropy(anisotropy);
+        self.settings.anisotropy = self.sampler_cache.anisotropy();
+
+        let mut models = self.geom_models.iter_mut().map(|geom| &mut geom.model).collect::<Vec<_>>();
+        models.push(&mut self.light_model);
+        #[cfg(not(target_arch = "wasm32"))]
+        models.push(&mut self.fog_model);
+        for model in models {
+            for material in &mut model.materials {
+                material.update_samplers(&self.device, &self.texture_bind_group_layout, &mut self.sampler_cache);
+            }
+        }
+    }
+
+    /// Rebuilds the pipelines whose shaders changed on disk, if hot reload is
+    /// enabled. Pipelines that fail to build keep using the old shader.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn reload_changed_shaders(&mut self) {
+        let Some(watcher) = &self.shader_watcher else {
+            return;
+        };
+        let changed = watcher.changed_files();
+        if changed.is_empty() {
+            return;
+        }
+        println!("shader watcher: {} changed", changed.join(", "));
+
+        let passes = self
+            .geometry_pass
+            .iter_mut()
+            .chain(self.geometry_mask_pass.iter_mut())
+            .chain(self.geometry_blend_pass.iter_mut())
+            .chain(self.light_depth_pass.iter_mut())
+            .chain(self.light_depth_mask_pass.iter_mut())
+            .chain([&mut self.light_debug_pass, &mut self.fog_pass]);
+        for pass in passes {
+            if !changed.iter().any(|file| pass.uses_shader_file(file)) {
+                continue;
+            }
+            let shader = match watcher.preprocess(pass.shader_name(), &pass.shader_defines()) {
+                Ok(shader) => shader,
+                Err(e) => {
+                    println!("shader watcher: can't load {}, keeping the old pipeline: {}", pass.shader_name(), e);
+                    continue;
+                }
+            };
+            match pass.reload(&self.device, shader) {
+                Ok(()) => println!("shader watcher: reloaded {}", pass.shader_name()),
+                Err(e) => println!("shader watcher: {} failed, keeping the old pipeline: {}", pass.shader_name(), e),
+            }
+        }
+    }
+
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
         if new_size.width > 0 && new_size.height > 0 {
             self.size = new_size;
             self.config.width = new_size.width;
             self.config.height = new_size.height;
-            self.surface.configure(&self.device, &self.config);
+            match &mut self.target {
+                RenderTarget::Surface(surface) => surface.configure(&self.device, &self.config),
+                RenderTarget::Offscreen(texture) => *texture = State::create_offscreen_texture(&self.device, &self.config),
+            }
             self.camera
                 .projection
                 .resize(new_size.width, new_size.height);
@@ -596,6 +799,9 @@ impl State {
     }
 
     pub fn update(&mut self, dt: Duration, time: Duration) {
+        #[cfg(not(target_arch = "wasm32"))]
+        self.reload_changed_shaders();
+
         // Update camera
         self.camera.update(dt, &self.camera_controller);
         self.camera_controller.reset(false);
@@ -607,14 +813,16 @@ impl State {
         );
 
         // Update the light
-        self.light_uniform.position[0] = f32::sin(time.as_secs_f32() * 0.5) * 500.0;
-        self.light_uniform.position[1] = 250.0 + f32::sin(time.as_secs_f32() * 0.3) * 200.0;
-        self.light_uniform.position[2] = f32::sin(time.as_secs_f32() * 0.8) * 100.0;
-        self.light_uniform.update_matrices();
+        if self.light_animated {
+            self.light_uniform.position[0] = f32::sin(time.as_secs_f32() * 0.5) * 500.0;
+            self.light_uniform.position[1] = 250.0 + f32::sin(time.as_secs_f32() * 0.3) * 200.0;
+            self.light_uniform.position[2] = f32::sin(time.as_secs_f32() * 0.8) * 100.0;
+            self.light_uniform.update_matrices();
 
-        self.light_uniform.color[0] = f32::abs(f32::sin(time.as_secs_f32() * 1.0));
-        self.light_uniform.color[1] = f32::abs(f32::sin(time.as_secs_f32() * 0.6));
-        self.light_uniform.color[2] = f32::abs(f32::sin(time.as_secs_f32() * 0.4));
+            self.light_uniform.color[0] = f32::abs(f32::sin(time.as_secs_f32() * 1.0));
+            self.light_uniform.color[1] = f32::abs(f32::sin(time.as_secs_f32() * 0.6));
+            self.light_uniform.color[2] = f32::abs(f32::sin(time.as_secs_f32() * 0.4));
+        }
 
         self.queue.write_buffer(
             &self.light_buffer,
@@ -624,13 +832,43 @@ impl State {
 
         // Global uniforms
         self.global_uniforms.time = time.as_secs_f32();
-        self.global_uniforms.use_shadowmaps = if cfg!(target_arch = "wasm32") { 0u32 } else { 1u32 };
+    }
+
+    /// Meshes with blended materials, sorted back to front from the camera.
+    fn sorted_blend_meshes(&self) -> Vec<(&InstancedModel, u32, &Mesh)> {
+        let camera_position = self.camera.position.to_vec();
+        let mut meshes = Vec::new();
+        for geom in &self.geom_models {
+            for mesh in &geom.model.meshes {
+                if geom.model.materials[mesh.material].alpha_mode != AlphaMode::Blend {
+                    continue;
+                }
+                for (i, instance) in geom.instances.iter().enumerate() {
+                    let center = cgmath::Matrix4::from(instance.to_raw().model_matrix)
+                        .transform_point(mesh.center.into())
+                        .to_vec();
+                    let distance = (center - camera_position).magnitude2();
+                    meshes.push((distance, geom, i as u32, mesh));
+                }
+            }
+        }
+        meshes.sort_by(|a, b| b.0.total_cmp(&a.0));
+        meshes.into_iter().map(|(_, geom, i, mesh)| (geom, i, mesh)).collect()
     }
 
     pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
 
+        if !self.settings.shadows {
+            self.queue.write_buffer(
+                &self.global_uniforms_buffer,
+                0,
+                bytemuck::cast_slice(&[self.global_uniforms]),
+            );
+        }
+
         // render light to depth textures
-        for i in 0..SHADOW_MAP_LAYERS as usize {
+        let shadow_map_layers = if self.settings.shadows { SHADOW_MAP_LAYERS } else { 0 };
+        for i in 0..shadow_map_layers as usize {
             self.global_uniforms.light_matrix_index = i as u32;
             self.queue.write_buffer(
                 &self.global_uniforms_buffer,
@@ -661,24 +899,50 @@ impl State {
                         occlusion_query_set: None,
                     });
 
-                light_depth_render_pass.set_vertex_buffer(1, self.geom_instance_buffer.slice(..));
-                light_depth_render_pass.set_pipeline(&self.light_depth_pass.pipeline);
-                light_depth_render_pass.draw_model_instanced(
-                    &self.geom_model,
-                    0..self.geom_instances.len() as u32,
-                    [&self.global_bind_group].into(),
-                    false,
-                );
+                for (passes, alpha_mask) in [(&self.light_depth_pass, false), (&self.light_depth_mask_pass, true)] {
+                    for double_sided in [false, true] {
+                        light_depth_render_pass.set_pipeline(&passes.get(double_sided).pipeline);
+                        for geom in &self.geom_models {
+                            light_depth_render_pass.set_vertex_buffer(1, geom.instance_buffer.slice(..));
+                            for mesh in &geom.model.meshes {
+                                let material = &geom.model.materials[mesh.material];
+                                if (material.alpha_mode == AlphaMode::Mask) != alpha_mask
+                                    || material.double_sided != double_sided
+                                {
+                                    continue;
+                                }
+                                light_depth_render_pass.draw_mesh_instanced(
+                                    mesh,
+                                    material,
+                                    0..geom.instances.len() as u32,
+                                    [&self.global_bind_group].into(),
+                                    true,
+                                );
+                            }
+                        }
+                    }
+                }
             }
 
             self.queue.submit(std::iter::once(depth_encoder.finish()));
         }
 
         // render geometry
-        let surface_texture = self.surface.get_current_texture()?;
-        let surface_view = surface_texture
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
+        let (surface_texture, surface_view) = match &self.target {
+            RenderTarget::Surface(surface) => {
+                let surface_texture = surface.get_current_texture()?;
+                let surface_view = surface_texture
+                    .texture
+                    .create_view(&wgpu::TextureViewDescriptor::default());
+                (Some(surface_texture), surface_view)
+            }
+            RenderTarget::Offscreen(texture) => (
+                None,
+                texture
+                    .texture
+                    .create_view(&wgpu::TextureViewDescriptor::default()),
+            ),
+        };
         let mut geometry_encoder = self
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor {
@@ -714,19 +978,46 @@ impl State {
                 occlusion_query_set: None,
             });
 
-            geom_render_pass.set_vertex_buffer(1, self.geom_instance_buffer.slice(..));
-            geom_render_pass.set_pipeline(&self.geometry_pass.pipeline);
-            geom_render_pass.draw_model_instanced(
-                &self.geom_model,
-                0..self.geom_instances.len() as u32,
-                [&self.global_bind_group, &self.light_depth_bind_group].into(),
-                true,
-            );
+            for (passes, alpha_mode) in [(&self.geometry_pass, AlphaMode::Opaque), (&self.geometry_mask_pass, AlphaMode::Mask)] {
+                for double_sided in [false, true] {
+                    geom_render_pass.set_pipeline(&passes.get(double_sided).pipeline);
+                    for geom in &self.geom_models {
+                        geom_render_pass.set_vertex_buffer(1, geom.instance_buffer.slice(..));
+                        for mesh in &geom.model.meshes {
+                            let material = &geom.model.materials[mesh.material];
+                            if material.alpha_mode != alpha_mode || material.double_sided != double_sided {
+                                continue;
+                            }
+                            geom_render_pass.draw_mesh_instanced(
+                                mesh,
+                                material,
+                                0..geom.instances.len() as u32,
+                                [&self.global_bind_group, &self.light_depth_bind_group].into(),
+                                true,
+                            );
+                        }
+                    }
+                }
+            }
+
+            // blended meshes last, back to front
+            for (geom, instance, mesh) in self.sorted_blend_meshes() {
+                let material = &geom.model.materials[mesh.material];
+                geom_render_pass.set_pipeline(&self.geometry_blend_pass.get(material.double_sided).pipeline);
+                geom_render_pass.set_vertex_buffer(1, geom.instance_buffer.slice(..));
+                geom_render_pass.draw_mesh_instanced(
+                    mesh,
+                    material,
+                    instance..instance + 1,
+                    [&self.global_bind_group, &self.light_depth_bind_group].into(),
+                    true,
+                );
+            }
         }
         geometry_encoder.pop_debug_group();
 
         geometry_encoder.push_debug_group("debug light pass");
-        {
+        if self.settings.light_debug {
             let mut light_debug_render_pass =
                 geometry_encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                     label: Some("Light Debug Render Pass"),
@@ -761,7 +1052,7 @@ impl State {
         self.queue.submit(std::iter::once(geometry_encoder.finish()));
 
         #[cfg(not(target_arch = "wasm32"))]
-        {
+        if self.settings.fog && !self.fog_instances.is_empty() {
             let mut fog_encoder = self
                 .device
                 .create_command_encoder(&wgpu::CommandEncoderDescriptor {
@@ -780,11 +1071,9 @@ impl State {
                             store: wgpu::StoreOp::Store,
                         },
                     })],
-                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                        view: &self.geometry_depth_texture.view,
-                        depth_ops: None,
-                        stencil_ops: None,
-                    }),
+                    // geometry depth is sampled instead, some backends (GL)
+                    // can't sample a read-only depth attachment
+                    depth_stencil_attachment: None,
                     timestamp_writes: None,
                     occlusion_query_set: None,
                 });
@@ -803,7 +1092,25 @@ impl State {
             self.queue.submit(std::iter::once(fog_encoder.finish()));
         }
 
-        surface_texture.present();
+        #[cfg(not(target_arch = "wasm32"))]
+        if self.screenshot_requested {
+            self.screenshot_requested = false;
+            let texture = match (&surface_texture, &self.target) {
+                (Some(surface_texture), _) => &surface_texture.texture,
+                (None, RenderTarget::Offscreen(texture)) => &texture.texture,
+                (None, RenderTarget::Surface(_)) => unreachable!(),
+            };
+            let screenshot = if texture.usage().contains(wgpu::TextureUsages::COPY_SRC) {
+                Capture::read_texture(&self.device, &self.queue, texture, wgpu::TextureAspect::All)
+            } else {
+                Err(anyhow::anyhow!("screenshot: surface doesn't support COPY_SRC"))
+            };
+            self.screenshot = Some(screenshot);
+        }
+
+        if let Some(surface_texture) = surface_texture {
+            surface_texture.present();
+        }
 
         Ok(())
     }
@@ -1,10 +1,18 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
 use anyhow::*;
+use wgpu::util::DeviceExt;
+
+use crate::core::ktx::Ktx2Image;
+use crate::core::mipmap::MipmapGenerator;
 
+/// A texture & its default view. Samplers are kept by their users, material
+/// textures get theirs from `SamplerCache`.
 #[derive(Debug)]
 pub struct Texture {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
-    pub sampler: wgpu::Sampler,
 }
 
 impl Texture {
@@ -13,12 +21,10 @@ impl Texture {
     pub fn create_depth_texture(
         device: &wgpu::Device,
         label: &str,
-        compare: Option<wgpu::CompareFunction>,
         width: u32,
         height: u32,
         layers: u32,
         usage: wgpu::TextureUsages,
-        filter: bool,
     ) -> Self {
         let size = wgpu::Extent3d {
             width,
@@ -47,13 +53,37 @@ impl Texture {
             base_array_layer: 0,
             array_layer_count: if layers > 1 { Some(layers) } else { None },
         });
-        let sampler = Texture::create_sampler(device, compare, filter);
 
-        Self {
-            texture,
-            view,
-            sampler,
-        }
+        Self { texture, view }
+    }
+
+    pub fn create_render_target(
+        device: &wgpu::Device,
+        label: &str,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        usage: wgpu::TextureUsages,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage,
+            view_formats: &[],
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        Self { texture, view }
     }
 
     pub fn create_sampler(device: &wgpu::Device, compare: Option<wgpu::CompareFunction>, filter: bool) -> wgpu::Sampler {
@@ -71,6 +101,20 @@ impl Texture {
         })
     }
 
+    /// A 1x1 texture of a single color, for materials missing a map.
+    pub fn from_color(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        color: [u8; 4],
+        format: wgpu::TextureFormat,
+        label: Option<&str>,
+    ) -> Result<Self> {
+        Self::from_pixels(device, queue, &color, (1, 1), 4, format, None, label)
+    }
+
+    /// Uploads `pixels` as mip level 0, and generates the rest of the mip
+    /// chain if `mipmaps` is given.
+    #[allow(clippy::too_many_arguments)]
     pub fn from_pixels(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
@@ -78,6 +122,7 @@ impl Texture {
         dimensions: (u32, u32),
         stride: u32,
         format: wgpu::TextureFormat,
+        mipmaps: Option<&mut MipmapGenerator>,
         label: Option<&str>,
     ) -> Result<Self> {
         let size = wgpu::Extent3d {
@@ -85,14 +130,21 @@ impl Texture {
             height: dimensions.1,
             depth_or_array_layers: 1,
         };
+        let (mip_level_count, usage) = match mipmaps {
+            Some(..) => (
+                MipmapGenerator::mip_level_count(dimensions.0, dimensions.1),
+                wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::RENDER_ATTACHMENT,
+            ),
+            None => (1, wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST),
+        };
         let texture = device.create_texture(&wgpu::TextureDescriptor {
             label,
             size,
-            mip_level_count: 1,
+            mip_level_count,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
             format,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            usage,
             view_formats: &[],
         });
 
@@ -114,21 +166,145 @@ impl Texture {
             size,
         );
 
+        if let Some(mipmaps) = mipmaps {
+            mipmaps.generate(device, queue, &texture);
+        }
+
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+
+        Ok(Self { texture, view })
+    }
+
+    /// Uploads a KTX2 texture with the mip levels it has. Uncompressed RGBA8
+    /// without mips gets them generated if `mipmaps` is given.
+    pub fn from_ktx2(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        image: &Ktx2Image,
+        mipmaps: Option<&mut MipmapGenerator>,
+        label: Option<&str>,
+    ) -> Result<Self> {
+        let is_rgba8 = image.format.remove_srgb_suffix() == wgpu::TextureFormat::Rgba8Unorm;
+        if image.mip_level_count == 1 && is_rgba8 && mipmaps.is_some() {
+            return Self::from_pixels(
+                device,
+                queue,
+                &image.data,
+                (image.width, image.height),
+                4,
+                image.format,
+                mipmaps,
+                label,
+            );
+        }
+
+        let texture = device.create_texture_with_data(
+            queue,
+            &wgpu::TextureDescriptor {
+                label,
+                size: image.size(),
+                mip_level_count: image.mip_level_count,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: image.format,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            },
+            &image.data,
+        );
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        Ok(Self { texture, view })
+    }
+}
+
+/// Wrapping & filtering of a material texture, the parts of a sampler that
+/// glTF can set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SamplerKey {
+    pub address_mode_u: wgpu::AddressMode,
+    pub address_mode_v: wgpu::AddressMode,
+    pub mag_filter: wgpu::FilterMode,
+    pub min_filter: wgpu::FilterMode,
+    pub mipmap_filter: wgpu::FilterMode,
+    /// Whether levels below the base are sampled, `lod_max_clamp` is 0
+    /// otherwise.
+    pub mipmaps: bool,
+}
+
+impl Default for SamplerKey {
+    fn default() -> Self {
+        Self {
             address_mode_u: wgpu::AddressMode::Repeat,
             address_mode_v: wgpu::AddressMode::Repeat,
-            address_mode_w: wgpu::AddressMode::Repeat,
             mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            ..Default::default()
-        });
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            mipmaps: true,
+        }
+    }
+}
 
-        Ok(Self {
-            texture,
-            view,
-            sampler,
-        })
+/// Creates each distinct sampler once, so materials with the same sampler
+/// settings share it.
+#[derive(Debug)]
+pub struct SamplerCache {
+    samplers: HashMap<SamplerKey, Arc<wgpu::Sampler>>,
+    anisotropy: u16,
+}
+
+impl SamplerCache {
+    pub const MAX_ANISOTROPY: u16 = 16;
+
+    /// `anisotropy` is clamped to 1..=16, 1 disables anisotropic filtering.
+    pub fn new(anisotropy: u16) -> Self {
+        Self {
+            samplers: HashMap::new(),
+            anisotropy: anisotropy.clamp(1, Self::MAX_ANISOTROPY),
+        }
+    }
+
+    pub fn anisotropy(&self) -> u16 {
+        self.anisotropy
+    }
+
+    /// Samplers created before this keep the old level, textures need to
+    /// fetch theirs again with `get`.
+    pub fn set_anisotropy(&mut self, anisotropy: u16) {
+        let anisotropy = anisotropy.clamp(1, Self::MAX_ANISOTROPY);
+        if anisotropy != self.anisotropy {
+            self.anisotropy = anisotropy;
+            self.samplers.clear();
+        }
+    }
+
+    pub fn get(&mut self, device: &wgpu::Device, key: SamplerKey) -> Arc<wgpu::Sampler> {
+        // wgpu only allows anisotropy when every filter is linear
+        let anisotropy_clamp = if key.mag_filter == wgpu::FilterMode::Linear
+            && key.min_filter == wgpu::FilterMode::Linear
+            && key.mipmap_filter == wgpu::FilterMode::Linear
+        {
+            self.anisotropy
+        } else {
+            1
+        };
+
+        self.samplers
+            .entry(key)
+            .or_insert_with(|| {
+                Arc::new(device.create_sampler(&wgpu::SamplerDescriptor {
+                    label: Some("Material Sampler"),
+                    address_mode_u: key.address_mode_u,
+                    address_mode_v: key.address_mode_v,
+                    address_mode_w: wgpu::AddressMode::Repeat,
+                    mag_filter: key.mag_filter,
+                    min_filter: key.min_filter,
+                    mipmap_filter: key.mipmap_filter,
+                    lod_max_clamp: if key.mipmaps { 32.0 } else { 0.0 },
+                    anisotropy_clamp,
+                    ..Default::default()
+                }))
+            })
+            .clone()
     }
 }
@@ -1,3 +1,5 @@
+use super::scene::Scene;
+use super::settings::Settings;
 use super::state::State;
 use winit::{
     event::*,
@@ -6,31 +8,23 @@ use winit::{
 };
 use winit::keyboard::{KeyCode, PhysicalKey};
 
-#[cfg(debug_assertions)]
-fn create_window(event_loop: &EventLoop<()>) -> winit::window::Window {
+fn create_window(event_loop: &EventLoop<()>, settings: &Settings) -> winit::window::Window {
     log::info!("Creating window");
-    use winit::dpi::PhysicalSize;
-    WindowBuilder::new()
-        .with_inner_size(PhysicalSize::new(1920, 1080))
-        .with_fullscreen(Some(winit::window::Fullscreen::Borderless(None)))
-        .with_maximized(true)
-        .build(event_loop)
-        .unwrap()
-}
-
-#[cfg(not(debug_assertions))]
-fn create_window(event_loop: &EventLoop<()>) -> winit::window::Window {
-    log::info!("Creating window");
-    WindowBuilder::new()
-        .with_fullscreen(Some(winit::window::Fullscreen::Borderless(None)))
-        .with_maximized(true)
-        .build(event_loop)
-        .unwrap()
+    let mut builder = WindowBuilder::new();
+    if let Some((width, height)) = settings.window_size {
+        builder = builder.with_inner_size(winit::dpi::PhysicalSize::new(width, height));
+    }
+    if settings.fullscreen {
+        builder = builder
+            .with_fullscreen(Some(winit::window::Fullscreen::Borderless(None)))
+            .with_maximized(true);
+    }
+    builder.build(event_loop).unwrap()
 }
 
-pub async fn run() {
+pub async fn run(settings: Settings) {
     let event_loop = EventLoop::new().unwrap();
-    let window = create_window(&event_loop);
+    let window = create_window(&event_loop, &settings);
 
     #[cfg(target_arch = "wasm32")]
     {
@@ -46,7 +40,23 @@ pub async fn run() {
             .expect("Couldn't append canvas to document body.");
     }
 
-    let mut state = State::new(&window).await;
+    let scene = match &settings.model {
+        Some(model) => Scene::from_model(model),
+        None => match Scene::load(&settings.assets, &settings.scene).await {
+            Ok(scene) => scene,
+            Err(e) => {
+                log::error!("Failed to load scene {}: {e:?}", settings.scene);
+                return;
+            }
+        },
+    };
+    let mut state = match State::new(&window, &scene, &settings).await {
+        Ok(state) => state,
+        Err(e) => {
+            log::error!("Failed to create renderer: {e:?}");
+            return;
+        }
+    };
     let mut last_render = instant::Instant::now();
     let start_time = instant::Instant::now();
     let mut is_focused = true;
@@ -68,6 +78,8 @@ pub async fn run() {
                     state.update(dt, time);
                     match state.render() {
                         Ok(_) => {
+                            #[cfg(not(target_arch = "wasm32"))]
+                            save_screenshot(&mut state);
                             window.request_redraw();
                         }
                         // Reconfigure the surface if lost
@@ -89,35 +101,60 @@ pub async fn run() {
             Event::WindowEvent {
                 ref event,
                 window_id,
-            } if window_id == window.id() => {
-                if !state.input(Some(event), None) {
-                    match event {
-                        WindowEvent::CloseRequested
-                        | WindowEvent::KeyboardInput {
-                            event: KeyEvent {
-                                state: ElementState::Pressed,
-                                physical_key: PhysicalKey::Code(KeyCode::Escape),
-                                ..
-                            },
+            } if window_id == window.id() && !state.input(Some(event), None) => {
+                match event {
+                    WindowEvent::CloseRequested
+                    | WindowEvent::KeyboardInput {
+                        event: KeyEvent {
+                            state: ElementState::Pressed,
+                            physical_key: PhysicalKey::Code(KeyCode::Escape),
                             ..
-                        } => {
-                            #[cfg(not(target_arch = "wasm32"))]
-                            {
-                                window_target.exit();
-                            }
-                        }
-                        WindowEvent::Resized(physical_size) => {
-                            log::info!("WindowEvent::Resized {}:{}", physical_size.width, physical_size.height);
-                            state.resize(*physical_size);
-                            window.request_redraw();
-                        }
-                        WindowEvent::Focused(focused) => {
-                            lock_cursor(&window, *focused);
-                            is_focused = *focused;
-                            window.request_redraw();
+                        },
+                        ..
+                    } => {
+                        #[cfg(not(target_arch = "wasm32"))]
+                        {
+                            window_target.exit();
                         }
-                        _ => {}
                     }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    WindowEvent::KeyboardInput {
+                        event: KeyEvent {
+                            state: ElementState::Pressed,
+                            physical_key: PhysicalKey::Code(KeyCode::F12),
+                            repeat: false,
+                            ..
+                        },
+                        ..
+                    } => {
+                        state.request_screenshot();
+                    }
+                    WindowEvent::KeyboardInput {
+                        event: KeyEvent {
+                            state: ElementState::Pressed,
+                            physical_key: PhysicalKey::Code(KeyCode::KeyF),
+                            repeat: false,
+                            ..
+                        },
+                        ..
+                    } => {
+                        // 1, 2, 4, 8, 16, 1, ...
+                        let anisotropy = state.anisotropy();
+                        state.set_anisotropy(if anisotropy >= 16 { 1 } else { (anisotropy + 1).next_power_of_two() });
+                        log::info!("Anisotropic filtering {}x", state.anisotropy());
+                        window.request_redraw();
+                    }
+                    WindowEvent::Resized(physical_size) => {
+                        log::info!("WindowEvent::Resized {}:{}", physical_size.width, physical_size.height);
+                        state.resize(*physical_size);
+                        window.request_redraw();
+                    }
+                    WindowEvent::Focused(focused) => {
+                        lock_cursor(&window, *focused);
+                        is_focused = *focused;
+                        window.request_redraw();
+                    }
+                    _ => {}
                 }
             }
             _ => {}
@@ -125,6 +162,25 @@ pub async fn run() {
     }).unwrap();
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+fn save_screenshot(state: &mut State) {
+    match state.take_screenshot() {
+        Some(Ok(capture)) => {
+            let timestamp = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            let path = format!("screenshot_{}.{}", timestamp, capture.file_extension());
+            match capture.save(&path) {
+                Ok(_) => log::info!("Saved screenshot {}", path),
+                Err(e) => log::error!("Failed to save screenshot: {e:?}"),
+            }
+        }
+        Some(Err(e)) => log::error!("Failed to capture screenshot: {e:?}"),
+        None => {}
+    }
+}
+
 fn lock_cursor(window: &winit::window::Window, lock: bool) {
     if lock {
         if let Err(e) = window
@@ -1,20 +1,33 @@
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 
-mod core;
-mod shaders;
+pub mod core;
+pub mod shaders;
+
+use core::settings::Settings;
 
 #[cfg_attr(target_arch = "wasm32", wasm_bindgen(start))]
 pub fn run() {
+    let settings = Settings::default();
+    // the web version fetches scenes & models from next to the page
+    #[cfg(target_arch = "wasm32")]
+    let settings = Settings {
+        assets: core::resources::AssetSource::page_relative("res").unwrap_or_default(),
+        ..settings
+    };
+    run_with_settings(settings);
+}
+
+pub fn run_with_settings(settings: Settings) {
     #[cfg(target_arch = "wasm32")]
     {
         std::panic::set_hook(Box::new(console_error_panic_hook::hook));
         console_log::init().expect("Couldn't initialize logger");
-        wasm_bindgen_futures::spawn_local(core::window::run());
+        wasm_bindgen_futures::spawn_local(core::window::run(settings));
     }
     #[cfg(not(target_arch = "wasm32"))]
     {
         env_logger::init();
-        pollster::block_on(core::window::run());
+        pollster::block_on(core::window::run(settings));
     }
 }
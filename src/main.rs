@@ -1,5 +1,119 @@
-use wgpu_renderer::run;
+use std::path::PathBuf;
+
+use clap::{Parser, ValueEnum};
+use wgpu_renderer::core::resources::AssetSource;
+use wgpu_renderer::core::settings::{Settings, ShaderSettings};
+use wgpu_renderer::run_with_settings;
+
+#[derive(Parser)]
+#[command(about = "A small wgpu renderer")]
+struct Args {
+    /// Load assets from this directory instead of the embedded res/
+    #[arg(long)]
+    assets: Option<PathBuf>,
+
+    /// Scene file to load, relative to the assets
+    #[arg(long, default_value = "scenes/sponza.ron", conflicts_with = "model")]
+    scene: String,
+
+    /// View a single model (.glb, .gltf or .obj) instead of a scene, relative to the assets
+    #[arg(long)]
+    model: Option<String>,
+
+    /// Window size, e.g. 1920x1080
+    #[arg(long, value_parser = parse_size)]
+    size: Option<(u32, u32)>,
+
+    /// Run in a window instead of borderless fullscreen
+    #[arg(long)]
+    windowed: bool,
+
+    #[arg(long, value_enum, default_value_t = PresentMode::Fifo)]
+    present_mode: PresentMode,
+
+    #[arg(long, value_enum, default_value_t = Backend::Auto)]
+    backend: Backend,
+
+    /// Width & height of the shadow maps
+    #[arg(long, default_value_t = 2048)]
+    shadow_map_size: u32,
+
+    /// Anisotropic filtering level, 1 disables it
+    #[arg(long, default_value_t = 16, value_parser = clap::value_parser!(u16).range(1..=16))]
+    anisotropy: u16,
+
+    /// Disable volumetric fog
+    #[arg(long)]
+    no_fog: bool,
+
+    /// Disable shadow mapping
+    #[arg(long)]
+    no_shadows: bool,
+
+    /// Hide the light debug cube
+    #[arg(long)]
+    no_light_debug: bool,
+
+    /// Reload shaders from res/shaders in the source tree when they change
+    #[arg(long)]
+    hot_reload_shaders: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum PresentMode {
+    Fifo,
+    Mailbox,
+    Immediate,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Backend {
+    Auto,
+    Vulkan,
+    Gl,
+    Metal,
+    Dx12,
+}
+
+fn parse_size(s: &str) -> Result<(u32, u32), String> {
+    let (width, height) = s
+        .split_once('x')
+        .ok_or_else(|| format!("expected WIDTHxHEIGHT, got {s}"))?;
+    let width = width.parse().map_err(|e| format!("invalid width: {e}"))?;
+    let height = height.parse().map_err(|e| format!("invalid height: {e}"))?;
+    Ok((width, height))
+}
 
 fn main() {
-    run();
+    let args = Args::parse();
+    let default = Settings::default();
+
+    run_with_settings(Settings {
+        assets: args.assets.map_or(AssetSource::Embedded, AssetSource::Directory),
+        scene: args.scene,
+        model: args.model,
+        window_size: args.size,
+        fullscreen: !args.windowed,
+        present_mode: match args.present_mode {
+            PresentMode::Fifo => wgpu::PresentMode::Fifo,
+            PresentMode::Mailbox => wgpu::PresentMode::Mailbox,
+            PresentMode::Immediate => wgpu::PresentMode::Immediate,
+        },
+        backends: match args.backend {
+            Backend::Auto => default.backends,
+            Backend::Vulkan => wgpu::Backends::VULKAN,
+            Backend::Gl => wgpu::Backends::GL,
+            Backend::Metal => wgpu::Backends::METAL,
+            Backend::Dx12 => wgpu::Backends::DX12,
+        },
+        shader: ShaderSettings {
+            shadow_map_size: args.shadow_map_size,
+            ..default.shader
+        },
+        anisotropy: args.anisotropy,
+        fog: !args.no_fog,
+        shadows: !args.no_shadows,
+        light_debug: !args.no_light_debug,
+        hot_reload_shaders: args.hot_reload_shaders,
+    });
 }
@@ -0,0 +1,279 @@
+use std::collections::HashMap;
+
+/// Binary operators from lowest to highest precedence, as in C.
+const BINARY_OPERATORS: [&[&str]; 6] = [
+    &["||"],
+    &["&&"],
+    &["==", "!="],
+    &["<=", ">=", "<", ">"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+// longest first, so `<=` isn't read as `<`
+const OPERATORS: [&str; 16] = [
+    "||", "&&", "==", "!=", "<=", ">=", "<", ">", "+", "-", "*", "/", "%", "!", "(", ")",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token<'a> {
+    Number(i64),
+    Identifier(&'a str),
+    Operator(&'static str),
+}
+
+/// Evaluates the integer expression of an `#if`, like the C preprocessor:
+/// `defined(NAME)`, integers, defines with integer values, and the C
+/// arithmetic, comparison & logical operators. Undefined names are 0.
+pub fn evaluate(expression: &str, defines: &HashMap<String, String>) -> Result<i64, String> {
+    let tokens = tokenize(expression)?;
+    if tokens.is_empty() {
+        return Err("empty expression".to_string());
+    }
+
+    let mut parser = Parser {
+        tokens: &tokens,
+        position: 0,
+        defines,
+        skipping: false,
+    };
+    let value = parser.binary(0)?;
+    match parser.tokens.get(parser.position) {
+        Some(token) => Err(format!("unexpected {}", describe(token))),
+        None => Ok(value),
+    }
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token<'_>>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = expression.trim_start();
+    while !rest.is_empty() {
+        if let Some(operator) = OPERATORS.iter().find(|operator| rest.starts_with(**operator)) {
+            tokens.push(Token::Operator(operator));
+            rest = &rest[operator.len()..];
+        } else {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            let word = &rest[..end];
+            if word.is_empty() {
+                return Err(format!("unexpected {:?}", rest.chars().next().unwrap_or_default()));
+            } else if word.starts_with(|c: char| c.is_ascii_digit()) {
+                let number = word.parse().map_err(|_| format!("invalid number {}", word))?;
+                tokens.push(Token::Number(number));
+            } else {
+                tokens.push(Token::Identifier(word));
+            }
+            rest = &rest[end..];
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Number(number) => number.to_string(),
+        Token::Identifier(identifier) => identifier.to_string(),
+        Token::Operator(operator) => format!("'{}'", operator),
+    }
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token<'a>],
+    position: usize,
+    defines: &'a HashMap<String, String>,
+    /// Set in the right operand of a `||` or `&&` the left one decides, like
+    /// `defined(N) && 4 / N > 1`. It's still parsed, but evaluation errors
+    /// like a division by zero are ignored.
+    skipping: bool,
+}
+
+impl<'a> Parser<'a> {
+    fn next(&mut self) -> Option<Token<'a>> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn eat(&mut self, operator: &str) -> bool {
+        if matches!(self.tokens.get(self.position), Some(Token::Operator(o)) if *o == operator) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, operator: &str) -> Result<(), String> {
+        if self.eat(operator) {
+            Ok(())
+        } else {
+            Err(format!("expected '{}'", operator))
+        }
+    }
+
+    /// Operators of precedence `level` and up.
+    fn binary(&mut self, level: usize) -> Result<i64, String> {
+        let Some(operators) = BINARY_OPERATORS.get(level) else {
+            return self.unary();
+        };
+
+        let mut left = self.binary(level + 1)?;
+        while let Some(operator) = operators.iter().copied().find(|operator| self.eat(operator)) {
+            let decided = (operator == "||" && left != 0) || (operator == "&&" && left == 0);
+            let skipping = self.skipping;
+            self.skipping |= decided;
+            let right = self.binary(level + 1);
+            self.skipping = skipping;
+            let right = right?;
+            left = match operator {
+                "||" => (left != 0 || right != 0) as i64,
+                "&&" => (left != 0 && right != 0) as i64,
+                "==" => (left == right) as i64,
+                "!=" => (left != right) as i64,
+                "<=" => (left <= right) as i64,
+                ">=" => (left >= right) as i64,
+                "<" => (left < right) as i64,
+                ">" => (left > right) as i64,
+                "+" => left.wrapping_add(right),
+                "-" => left.wrapping_sub(right),
+                "*" => left.wrapping_mul(right),
+                "/" => self.quotient(left.checked_div(right))?,
+                "%" => self.quotient(left.checked_rem(right))?,
+                _ => unreachable!(),
+            };
+        }
+        Ok(left)
+    }
+
+    /// A checked `/` or `%`, 0 instead of an error while skipping.
+    fn quotient(&self, value: Option<i64>) -> Result<i64, String> {
+        match value {
+            Some(value) => Ok(value),
+            None if self.skipping => Ok(0),
+            None => Err("division by zero".to_string()),
+        }
+    }
+
+    fn unary(&mut self) -> Result<i64, String> {
+        if self.eat("!") {
+            Ok((self.unary()? == 0) as i64)
+        } else if self.eat("-") {
+            Ok(self.unary()?.wrapping_neg())
+        } else {
+            self.primary()
+        }
+    }
+
+    fn primary(&mut self) -> Result<i64, String> {
+        match self.next() {
+            Some(Token::Number(number)) => Ok(number),
+            Some(Token::Operator("(")) => {
+                let value = self.binary(0)?;
+                self.expect(")")?;
+                Ok(value)
+            }
+            Some(Token::Identifier("defined")) => {
+                let parenthesized = self.eat("(");
+                let name = match self.next() {
+                    Some(Token::Identifier(name)) => name,
+                    _ => return Err("expected a name after defined".to_string()),
+                };
+                if parenthesized {
+                    self.expect(")")?;
+                }
+                Ok(self.defines.contains_key(name) as i64)
+            }
+            Some(Token::Identifier(name)) => match self.defines.get(name) {
+                None => Ok(0),
+                Some(value) => match value.trim().parse() {
+                    Ok(value) => Ok(value),
+                    Err(_) if self.skipping => Ok(0),
+                    Err(_) => Err(format!("{} is {:?}, not an integer", name, value)),
+                },
+            },
+            Some(token) => Err(format!("unexpected {}", describe(&token))),
+            None => Err("unexpected end of expression".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(expression: &str, defines: &[(&str, &str)]) -> Result<i64, String> {
+        let defines = defines
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        evaluate(expression, &defines)
+    }
+
+    #[test]
+    fn defined_with_and_without_parentheses() {
+        let defines = [("X", "")];
+        assert_eq!(eval("defined X", &defines), Ok(1));
+        assert_eq!(eval("defined(X)", &defines), Ok(1));
+        assert_eq!(eval("defined Y", &defines), Ok(0));
+        assert_eq!(eval("defined(Y)", &defines), Ok(0));
+        assert_eq!(eval("defined X && !defined(Y)", &defines), Ok(1));
+        assert!(eval("defined(X", &defines).is_err());
+        assert!(eval("defined 1", &defines).is_err());
+    }
+
+    #[test]
+    fn operator_precedence() {
+        assert_eq!(eval("1 + 2 * 3", &[]), Ok(7));
+        assert_eq!(eval("(1 + 2) * 3", &[]), Ok(9));
+        assert_eq!(eval("10 - 4 - 3", &[]), Ok(3));
+        assert_eq!(eval("7 % 4 * 2", &[]), Ok(6));
+        assert_eq!(eval("-2 * 3", &[]), Ok(-6));
+        assert_eq!(eval("!0 + 1", &[]), Ok(2));
+        assert_eq!(eval("2 + 3 == 5", &[]), Ok(1));
+        assert_eq!(eval("1 < 2 == 1", &[]), Ok(1));
+        assert_eq!(eval("1 || 0 && 0", &[]), Ok(1));
+        assert_eq!(eval("(1 || 0) && 0", &[]), Ok(0));
+    }
+
+    #[test]
+    fn define_values() {
+        let defines = [("SAMPLES", "2"), ("NAME", "abc")];
+        assert_eq!(eval("SAMPLES * 2 + 1", &defines), Ok(5));
+        assert_eq!(eval("UNDEFINED + 1", &defines), Ok(1));
+        assert!(eval("NAME == 1", &defines).is_err());
+    }
+
+    #[test]
+    fn division_by_zero() {
+        assert_eq!(eval("1 / 0", &[]), Err("division by zero".to_string()));
+        assert_eq!(eval("1 % 0", &[]), Err("division by zero".to_string()));
+        assert_eq!(eval("4 / (X - 2)", &[("X", "2")]), Err("division by zero".to_string()));
+        assert_eq!(eval("4 / (X - 2)", &[("X", "4")]), Ok(2));
+    }
+
+    #[test]
+    fn logical_operators_short_circuit() {
+        assert_eq!(eval("defined(N) && 4 / N > 1", &[]), Ok(0));
+        assert_eq!(eval("defined(N) && 4 / N > 1", &[("N", "2")]), Ok(1));
+        assert_eq!(eval("!defined(N) || 4 / N > 1", &[]), Ok(1));
+        assert_eq!(eval("1 || 1 % 0 || 1 / 0", &[]), Ok(1));
+        assert_eq!(eval("0 && NAME", &[("NAME", "abc")]), Ok(0));
+        // only the skipped operand's errors are ignored
+        assert_eq!(eval("(0 && 1 / 0) + 1 / 0", &[]), Err("division by zero".to_string()));
+        assert_eq!(eval("0 || 1 / 0", &[]), Err("division by zero".to_string()));
+        // it's still parsed
+        assert!(eval("0 && (1", &[]).is_err());
+        assert!(eval("1 || 1 +", &[]).is_err());
+    }
+
+    #[test]
+    fn malformed_expressions() {
+        assert!(eval("", &[]).is_err());
+        assert!(eval("1 +", &[]).is_err());
+        assert!(eval("(1", &[]).is_err());
+        assert!(eval("1 2", &[]).is_err());
+        assert!(eval("1 $ 2", &[]).is_err());
+    }
+}
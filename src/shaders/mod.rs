@@ -1 +1,3 @@
-pub mod preprocessor;
\ No newline at end of file
+pub mod expression;
+pub mod preprocessor;
+pub mod structs;
@@ -1,23 +1,516 @@
-use wgpu;
-use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 
-use crate::core::resources::load_string;
+use regex::{Captures, Regex};
 
-pub fn preprocess_wgsl(filename: &str) -> wgpu::ShaderSource {
-    let source_path = "shaders/".to_owned() + filename;
-    println!("preprocess_wgsl: loading source {}", source_path);
-    let mut source = load_string(&source_path);
+use crate::core::resources::{load_string, AssetError};
+use crate::shaders::{expression, structs};
 
-    let re = Regex::new(r"#include (.*?)\n").unwrap();
-    for cap in re.captures_iter(&source.clone()) {
-        let whole_match = &cap[0];
-        let mut full_path: String = source_path.to_owned();
-        full_path = full_path.replace(filename, &cap[1]);
+/// WGSL source with the includes resolved.
+#[derive(Debug, Clone)]
+pub struct PreprocessedShader {
+    pub source: String,
+    /// The shader and the files it includes, relative to `shaders/`.
+    pub files: Vec<String>,
+    /// Where each line of `source` came from.
+    pub lines: Vec<SourceLine>,
+}
+
+/// A line in one of the files of a shader.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceLine {
+    /// Index into `PreprocessedShader::files`.
+    pub file: usize,
+    /// 1-based, like editors show them.
+    pub line: usize,
+}
+
+impl PreprocessedShader {
+    /// The file & line a 1-based line of `source` came from.
+    pub fn source_location(&self, line: usize) -> Option<(&str, usize)> {
+        let source_line = self.lines.get(line.checked_sub(1)?)?;
+        Some((&self.files[source_line.file], source_line.line))
+    }
+
+    /// Parses & validates the shader with naga, with errors pointing at the
+    /// files it was preprocessed from instead of the merged source.
+    pub fn validate(&self) -> Result<(naga::Module, naga::valid::ModuleInfo), String> {
+        let module = naga::front::wgsl::parse_str(&self.source)
+            .map_err(|e| self.error_at(e.location(&self.source), e.message()))?;
+        let info = naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::all())
+            .validate(&module)
+            .map_err(|e| self.error_at(e.location(&self.source), &error_chain(e.as_inner())))?;
+        Ok((module, info))
+    }
+
+    fn error_at(&self, location: Option<naga::SourceLocation>, message: &str) -> String {
+        let Some(location) = location else {
+            return format!("{}: {}", self.files[0], message);
+        };
+        match self.source_location(location.line_number as usize) {
+            Some((file, line)) => format!("{}:{}:{}: {}", file, line, location.line_position, message),
+            None => format!("{}: {}", self.files[0], message),
+        }
+    }
+}
+
+/// An error and its sources, naga puts the details in the sources.
+fn error_chain(error: &dyn std::error::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(error) = source {
+        message += &format!(": {}", error);
+        source = error.source();
+    }
+    message
+}
+
+#[derive(Debug)]
+pub enum PreprocessError {
+    Asset(AssetError),
+    /// A directive that can't be parsed, an `#if` without an `#endif`, or an
+    /// include cycle.
+    Syntax { file: String, line: usize, message: String },
+}
+
+impl fmt::Display for PreprocessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PreprocessError::Asset(e) => write!(f, "{}", e),
+            PreprocessError::Syntax { file, line, message } => write!(f, "{}:{}: {}", file, line, message),
+        }
+    }
+}
+
+impl std::error::Error for PreprocessError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PreprocessError::Asset(e) => Some(e),
+            PreprocessError::Syntax { .. } => None,
+        }
+    }
+}
+
+impl From<AssetError> for PreprocessError {
+    fn from(e: AssetError) -> Self {
+        PreprocessError::Asset(e)
+    }
+}
+
+/// Preprocesses `filename` from the embedded shaders.
+pub fn preprocess_wgsl(filename: &str, defines: &[(&str, &str)]) -> Result<PreprocessedShader, PreprocessError> {
+    preprocess_wgsl_with(filename, defines, load_string)
+}
+
+/// Preprocesses `filename`, loading the files with `load` instead of from the
+/// embedded assets.
+///
+/// Supports `#include file`, where `structs.wgsl` is generated from the Rust
+/// types shared with the shaders (see [`structs`]), `#pragma once`, `#define NAME [value]`,
+/// `#undef NAME`, and `#ifdef`/`#ifndef`/`#if`/`#elif`/`#else`/`#endif`
+/// blocks. `defines` are set before the first line. Defines with a value are
+/// replaced in the code.
+pub fn preprocess_wgsl_with(
+    filename: &str,
+    defines: &[(&str, &str)],
+    load: impl Fn(&str) -> Result<String, AssetError>,
+) -> Result<PreprocessedShader, PreprocessError> {
+    let mut preprocessor = Preprocessor {
+        defines: defines
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect(),
+        files: Vec::new(),
+        lines: Vec::new(),
+        include_stack: Vec::new(),
+        once: HashSet::new(),
+        identifier: Regex::new(r"[A-Za-z_][A-Za-z0-9_]*").unwrap(),
+    };
+    let mut source = String::new();
+    preprocessor.process_file(filename, &load, &mut source)?;
+
+    Ok(PreprocessedShader {
+        source,
+        files: preprocessor.files,
+        lines: preprocessor.lines,
+    })
+}
+
+/// An `#if` block being processed.
+struct Conditional {
+    /// Lines of the current branch are kept.
+    active: bool,
+    /// A branch was kept already, the rest are skipped.
+    taken: bool,
+    /// The block isn't inside a skipped branch.
+    parent_active: bool,
+    has_else: bool,
+    line: usize,
+}
+
+struct Preprocessor {
+    defines: HashMap<String, String>,
+    files: Vec<String>,
+    lines: Vec<SourceLine>,
+    /// The files being processed, to detect cycles.
+    include_stack: Vec<String>,
+    /// Files with `#pragma once`.
+    once: HashSet<String>,
+    identifier: Regex,
+}
+
+impl Preprocessor {
+    fn process_file(
+        &mut self,
+        filename: &str,
+        load: &impl Fn(&str) -> Result<String, AssetError>,
+        output: &mut String,
+    ) -> Result<(), PreprocessError> {
+        // the declarations generated from the Rust types aren't a file
+        let source = if filename == structs::FILE {
+            structs::source()
+        } else {
+            let source_path = "shaders/".to_owned() + filename;
+            println!("preprocess_wgsl: loading source {}", source_path);
+            load(&source_path)?
+        };
+        let file_index = match self.files.iter().position(|f| f == filename) {
+            Some(index) => index,
+            None => {
+                self.files.push(filename.to_string());
+                self.files.len() - 1
+            }
+        };
+        self.include_stack.push(filename.to_string());
+
+        let mut conditionals: Vec<Conditional> = Vec::new();
+        for (index, line) in source.lines().enumerate() {
+            let line_number = index + 1;
+            let error = |message: String| PreprocessError::Syntax {
+                file: filename.to_string(),
+                line: line_number,
+                message,
+            };
+            let active = conditionals.last().map_or(true, |c| c.active);
+
+            let Some(directive) = line.trim_start().strip_prefix('#') else {
+                if active {
+                    output.push_str(&self.substitute(line));
+                    output.push('\n');
+                    self.lines.push(SourceLine {
+                        file: file_index,
+                        line: line_number,
+                    });
+                }
+                continue;
+            };
+            let directive = directive.trim();
+            let (name, argument) = match directive.split_once(char::is_whitespace) {
+                Some((name, argument)) => (name, argument.trim()),
+                None => (directive, ""),
+            };
+
+            match name {
+                "ifdef" | "ifndef" | "if" => {
+                    // skipped blocks aren't evaluated, they may use undefined names
+                    let condition = active && self.condition(name, argument).map_err(error)?;
+                    conditionals.push(Conditional {
+                        active: condition,
+                        taken: condition,
+                        parent_active: active,
+                        has_else: false,
+                        line: line_number,
+                    });
+                }
+                "elif" => {
+                    let conditional = conditionals
+                        .last_mut()
+                        .ok_or_else(|| error("#elif without #if".to_string()))?;
+                    if conditional.has_else {
+                        return Err(error("#elif after #else".to_string()));
+                    }
+                    let condition = conditional.parent_active
+                        && !conditional.taken
+                        && self.condition("if", argument).map_err(error)?;
+                    conditional.active = condition;
+                    conditional.taken |= condition;
+                }
+                "else" => {
+                    let conditional = conditionals
+                        .last_mut()
+                        .ok_or_else(|| error("#else without #if".to_string()))?;
+                    if conditional.has_else {
+                        return Err(error("#else after #else".to_string()));
+                    }
+                    conditional.has_else = true;
+                    conditional.active = conditional.parent_active && !conditional.taken;
+                    conditional.taken = true;
+                }
+                "endif" => {
+                    conditionals
+                        .pop()
+                        .ok_or_else(|| error("#endif without #if".to_string()))?;
+                }
+                // everything else is skipped along with the code
+                _ if !active => {}
+                "define" => {
+                    let (define, value) = match argument.split_once(char::is_whitespace) {
+                        Some((define, value)) => (define, value.trim()),
+                        None => (argument, ""),
+                    };
+                    let define = self.name(define).map_err(error)?;
+                    self.defines.insert(define.to_string(), value.to_string());
+                }
+                "undef" => {
+                    let define = self.name(argument).map_err(error)?;
+                    self.defines.remove(define);
+                }
+                "include" => {
+                    if self.once.contains(argument) {
+                        continue;
+                    }
+                    if self.include_stack.iter().any(|f| f == argument) {
+                        return Err(error(format!(
+                            "include cycle {} -> {}",
+                            self.include_stack.join(" -> "),
+                            argument
+                        )));
+                    }
+                    println!("preprocess_wgsl: including {}", argument);
+                    self.process_file(argument, load, output)?;
+                }
+                "pragma" => match argument {
+                    "once" => {
+                        self.once.insert(filename.to_string());
+                    }
+                    _ => return Err(error(format!("unknown pragma {}", argument))),
+                },
+                _ => return Err(error(format!("unknown directive #{}", name))),
+            }
+        }
+
+        if let Some(conditional) = conditionals.last() {
+            return Err(PreprocessError::Syntax {
+                file: filename.to_string(),
+                line: conditional.line,
+                message: "#if without #endif".to_string(),
+            });
+        }
+        self.include_stack.pop();
+        Ok(())
+    }
 
-        println!("preprocess_wgsl: replacing {} with file {}", whole_match, full_path);
-        let nested_source = load_string(&full_path);
-        source = source.replace(whole_match, &nested_source);
+    /// Evaluates the argument of an `#ifdef`, `#ifndef` or `#if`.
+    fn condition(&self, directive: &str, argument: &str) -> Result<bool, String> {
+        match directive {
+            "ifdef" => Ok(self.defines.contains_key(self.name(argument)?)),
+            "ifndef" => Ok(!self.defines.contains_key(self.name(argument)?)),
+            _ => expression::evaluate(argument, &self.defines).map(|value| value != 0),
+        }
     }
 
-    wgpu::ShaderSource::Wgsl(source.into())
+    fn name<'a>(&self, argument: &'a str) -> Result<&'a str, String> {
+        match self.identifier.find(argument) {
+            Some(m) if m.start() == 0 && m.end() == argument.len() => Ok(argument),
+            _ => Err(format!("expected a name, got {:?}", argument)),
+        }
+    }
+
+    /// Replaces the defines that have a value in a line of code.
+    fn substitute(&self, line: &str) -> String {
+        if self.defines.values().all(String::is_empty) {
+            return line.to_string();
+        }
+        self.identifier
+            .replace_all(line, |captures: &Captures| match self.defines.get(&captures[0]) {
+                Some(value) if !value.is_empty() => value.clone(),
+                _ => captures[0].to_string(),
+            })
+            .into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Preprocesses the first of `files`, with all of them includable by name.
+    fn preprocess(files: &[(&str, &str)], defines: &[(&str, &str)]) -> Result<PreprocessedShader, PreprocessError> {
+        let sources = files
+            .iter()
+            .map(|(name, source)| (format!("shaders/{}", name), source.to_string()))
+            .collect::<HashMap<_, _>>();
+        preprocess_wgsl_with(files[0].0, defines, |path| {
+            sources.get(path).cloned().ok_or_else(|| AssetError::NotFound(path.to_string()))
+        })
+    }
+
+    fn lines(shader: &PreprocessedShader) -> Vec<&str> {
+        shader.source.lines().collect()
+    }
+
+    /// The file, line & message of a syntax error.
+    fn syntax_error(result: Result<PreprocessedShader, PreprocessError>) -> (String, usize, String) {
+        match result {
+            Err(PreprocessError::Syntax { file, line, message }) => (file, line, message),
+            other => panic!("expected a syntax error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn nested_and_skipped_blocks() {
+        let source = "\
+#ifdef A
+a
+#ifdef B
+a_b
+#else
+a_not_b
+#endif
+#endif
+#ifdef C
+#if broken(
+#define D
+#endif
+#endif
+#ifdef D
+d
+#endif
+end";
+        let shader = preprocess(&[("main.wgsl", source)], &[("A", "")]).unwrap();
+        assert_eq!(lines(&shader), ["a", "a_not_b", "end"]);
+
+        let shader = preprocess(&[("main.wgsl", source)], &[("B", "")]).unwrap();
+        assert_eq!(lines(&shader), ["end"]);
+    }
+
+    #[test]
+    fn elif_after_taken_branch() {
+        let source = "\
+#if X == 1
+one
+#elif X >= 1
+one_or_more
+#elif 1
+fallback
+#else
+none
+#endif";
+        let shader = preprocess(&[("main.wgsl", source)], &[("X", "1")]).unwrap();
+        assert_eq!(lines(&shader), ["one"]);
+
+        let shader = preprocess(&[("main.wgsl", source)], &[("X", "2")]).unwrap();
+        assert_eq!(lines(&shader), ["one_or_more"]);
+
+        let shader = preprocess(&[("main.wgsl", source)], &[("X", "0")]).unwrap();
+        assert_eq!(lines(&shader), ["fallback"]);
+    }
+
+    #[test]
+    fn else_after_else() {
+        let source = "#ifdef A\na\n#else\nb\n#else\nc\n#endif";
+        let (file, line, message) = syntax_error(preprocess(&[("main.wgsl", source)], &[]));
+        assert_eq!((file.as_str(), line, message.as_str()), ("main.wgsl", 5, "#else after #else"));
+
+        let source = "#ifdef A\na\n#else\nb\n#elif 1\nc\n#endif";
+        let (_, line, message) = syntax_error(preprocess(&[("main.wgsl", source)], &[]));
+        assert_eq!((line, message.as_str()), (5, "#elif after #else"));
+    }
+
+    #[test]
+    fn unterminated_if() {
+        // reported at the #if that isn't closed
+        let source = "#ifdef A\na\n#endif\n#if 1\n#ifdef B\nb\n#endif\nc";
+        let (file, line, message) = syntax_error(preprocess(&[("main.wgsl", source)], &[]));
+        assert_eq!((file.as_str(), line, message.as_str()), ("main.wgsl", 4, "#if without #endif"));
+
+        let (_, line, message) = syntax_error(preprocess(&[("main.wgsl", "a\n#endif")], &[]));
+        assert_eq!((line, message.as_str()), (2, "#endif without #if"));
+    }
+
+    #[test]
+    fn substitution_matches_whole_identifiers() {
+        let source = "\
+#define FOG_DENSITY_COLOR vec3<f32>(1.0)
+let density = FOG_DENSITY * FOG_DENSITY_COLOR * _FOG_DENSITY;";
+        let shader = preprocess(&[("main.wgsl", source)], &[("FOG_DENSITY", "2.0")]).unwrap();
+        assert_eq!(lines(&shader), ["let density = 2.0 * vec3<f32>(1.0) * _FOG_DENSITY;"]);
+    }
+
+    #[test]
+    fn define_and_undef() {
+        let source = "\
+#define SIZE 4
+#if SIZE > 2
+big
+#endif
+#undef SIZE
+#ifndef SIZE
+undefined
+#endif
+SIZE";
+        let shader = preprocess(&[("main.wgsl", source)], &[]).unwrap();
+        assert_eq!(lines(&shader), ["big", "undefined", "SIZE"]);
+    }
+
+    #[test]
+    fn include_cycle() {
+        let files = [
+            ("a.wgsl", "a\n#include b.wgsl"),
+            ("b.wgsl", "b\n\n#include a.wgsl"),
+        ];
+        let (file, line, message) = syntax_error(preprocess(&files, &[]));
+        assert_eq!(
+            (file.as_str(), line, message.as_str()),
+            ("b.wgsl", 3, "include cycle a.wgsl -> b.wgsl -> a.wgsl")
+        );
+    }
+
+    #[test]
+    fn pragma_once_file_is_included_once() {
+        let files = [
+            ("main.wgsl", "#include once.wgsl\n#include once.wgsl\nmain"),
+            ("once.wgsl", "#pragma once\nonce"),
+        ];
+        let shader = preprocess(&files, &[]).unwrap();
+        assert_eq!(lines(&shader), ["once", "main"]);
+        assert_eq!(shader.files, ["main.wgsl", "once.wgsl"]);
+    }
+
+    #[test]
+    fn file_without_pragma_is_included_every_time() {
+        let files = [
+            ("main.wgsl", "#include twice.wgsl\n#include twice.wgsl\nmain"),
+            ("twice.wgsl", "twice"),
+        ];
+        let shader = preprocess(&files, &[]).unwrap();
+        assert_eq!(lines(&shader), ["twice", "twice", "main"]);
+        assert_eq!(shader.files, ["main.wgsl", "twice.wgsl"]);
+    }
+
+    #[test]
+    fn errors_point_at_included_file() {
+        let files = [
+            (
+                "main.wgsl",
+                "// main\n#include included.wgsl\n\n@fragment\nfn fs_main() -> @location(0) vec4<f32> {\n    return vec4<f32>(value());\n}",
+            ),
+            ("included.wgsl", "// included\n\nfn value() -> f32 {\n    return undefined_value;\n}"),
+        ];
+        let shader = preprocess(&files, &[]).unwrap();
+        assert_eq!(shader.source_location(5), Some(("included.wgsl", 4)));
+        assert_eq!(shader.source_location(9), Some(("main.wgsl", 5)));
+
+        let error = shader.validate().unwrap_err();
+        assert!(error.starts_with("included.wgsl:4:"), "{}", error);
+    }
+
+    #[test]
+    fn missing_include() {
+        let files = [("main.wgsl", "#include missing.wgsl")];
+        match preprocess(&files, &[]) {
+            Err(PreprocessError::Asset(AssetError::NotFound(path))) => assert_eq!(path, "shaders/missing.wgsl"),
+            other => panic!("expected a missing asset, got {:?}", other),
+        }
+    }
 }
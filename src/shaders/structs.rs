@@ -0,0 +1,284 @@
+use std::mem;
+
+use crate::core::camera::CameraUniform;
+use crate::core::instance::InstanceRaw;
+use crate::core::light::LightUniform;
+use crate::core::material::MaterialUniform;
+use crate::core::model::ModelVertex;
+use crate::core::state::GlobalUniforms;
+
+/// The name the generated declarations are included with.
+pub const FILE: &str = "structs.wgsl";
+
+/// How the shaders use a struct, which decides the layout rules it follows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Usage {
+    /// `var<uniform>`, std140-like: struct & array members aligned to 16.
+    Uniform,
+    /// `var<storage>`, std430-like.
+    Storage,
+    /// Vertex shader input, one location per vector starting at
+    /// `first_location`, matrices take one per column.
+    Vertex { first_location: u32 },
+}
+
+/// A Rust type the shaders use, which the WGSL struct is generated from.
+/// Implemented with [`wgsl_struct!`].
+pub trait WgslStruct: bytemuck::Pod {
+    /// Name of the WGSL struct.
+    const NAME: &'static str;
+    const USAGE: Usage;
+
+    fn fields() -> Vec<Field>;
+}
+
+/// A Rust type with a WGSL equivalent.
+pub trait WgslType {
+    fn wgsl() -> String;
+
+    /// The vertex attributes it's split into, as count & WGSL type of each.
+    fn columns() -> (u32, String) {
+        (1, Self::wgsl())
+    }
+}
+
+macro_rules! wgsl_types {
+    ($($type:ty => $wgsl:literal),* $(,)?) => {
+        $(impl WgslType for $type {
+            fn wgsl() -> String {
+                $wgsl.to_string()
+            }
+        })*
+    };
+}
+
+wgsl_types! {
+    f32 => "f32",
+    u32 => "u32",
+    i32 => "i32",
+    [f32; 2] => "vec2<f32>",
+    [f32; 3] => "vec3<f32>",
+    [f32; 4] => "vec4<f32>",
+    [u32; 2] => "vec2<u32>",
+    [u32; 3] => "vec3<u32>",
+    [u32; 4] => "vec4<u32>",
+}
+
+impl<const C: usize, const R: usize> WgslType for [[f32; R]; C]
+where
+    [f32; R]: WgslType,
+{
+    fn wgsl() -> String {
+        format!("mat{}x{}<f32>", C, R)
+    }
+
+    fn columns() -> (u32, String) {
+        (C as u32, <[f32; R]>::wgsl())
+    }
+}
+
+impl<const N: usize> WgslType for [[[f32; 4]; 4]; N] {
+    fn wgsl() -> String {
+        format!("array<mat4x4<f32>, {}>", N)
+    }
+}
+
+/// Implements [`WgslStruct`] for a `#[repr(C)]` struct, listing the fields
+/// the WGSL struct has, in order.
+///
+/// ```text
+/// wgsl_struct!(ModelVertex as VertexInput, Usage::Vertex { first_location: 0 }, [position, tex_coords]);
+/// ```
+macro_rules! wgsl_struct {
+    ($type:ident, $usage:expr, [$($field:ident),* $(,)?]) => {
+        $crate::shaders::structs::wgsl_struct!($type as $type, $usage, [$($field),*]);
+    };
+    ($type:ident as $name:ident, $usage:expr, [$($field:ident),* $(,)?]) => {
+        impl $crate::shaders::structs::WgslStruct for $type {
+            const NAME: &'static str = stringify!($name);
+            const USAGE: $crate::shaders::structs::Usage = $usage;
+
+            fn fields() -> Vec<$crate::shaders::structs::Field> {
+                vec![$($crate::shaders::structs::Field::of(
+                    stringify!($field),
+                    std::mem::offset_of!($type, $field),
+                    |s: &$type| &s.$field,
+                )),*]
+            }
+        }
+    };
+}
+pub(crate) use wgsl_struct;
+
+/// A field of a [`WgslStruct`].
+#[derive(Debug, Clone)]
+pub struct Field {
+    pub name: &'static str,
+    /// WGSL type.
+    pub ty: String,
+    /// Offset in the Rust struct.
+    pub offset: usize,
+    pub size: usize,
+    /// See [`WgslType::columns`].
+    pub columns: (u32, String),
+}
+
+impl Field {
+    /// `field` only picks the type of the field, e.g. `|s: &S| &s.field`.
+    pub fn of<S, T: WgslType>(name: &'static str, offset: usize, _field: fn(&S) -> &T) -> Self {
+        Self {
+            name,
+            ty: T::wgsl(),
+            offset,
+            size: mem::size_of::<T>(),
+            columns: T::columns(),
+        }
+    }
+}
+
+/// A vertex attribute of a [`Usage::Vertex`] struct.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VertexLocation {
+    pub name: String,
+    pub location: u32,
+    pub offset: usize,
+    /// WGSL type.
+    pub ty: String,
+}
+
+/// A registered [`WgslStruct`].
+#[derive(Debug, Clone)]
+pub struct StructInfo {
+    pub name: &'static str,
+    pub usage: Usage,
+    pub fields: Vec<Field>,
+    /// Size of the Rust struct.
+    pub size: usize,
+}
+
+impl StructInfo {
+    pub fn of<T: WgslStruct>() -> Self {
+        Self {
+            name: T::NAME,
+            usage: T::USAGE,
+            fields: T::fields(),
+            size: mem::size_of::<T>(),
+        }
+    }
+
+    /// The vertex attributes, matrices split into `{name}_{column}`.
+    pub fn vertex_locations(&self) -> Vec<VertexLocation> {
+        let Usage::Vertex { first_location } = self.usage else {
+            return Vec::new();
+        };
+        let mut locations = Vec::new();
+        for field in &self.fields {
+            let (count, ty) = &field.columns;
+            if *count == 1 {
+                locations.push(VertexLocation {
+                    name: field.name.to_string(),
+                    location: first_location + locations.len() as u32,
+                    offset: field.offset,
+                    ty: ty.clone(),
+                });
+                continue;
+            }
+            let column_size = field.size / *count as usize;
+            for column in 0..*count as usize {
+                locations.push(VertexLocation {
+                    name: format!("{}_{}", field.name, column),
+                    location: first_location + locations.len() as u32,
+                    offset: field.offset + column * column_size,
+                    ty: ty.clone(),
+                });
+            }
+        }
+        locations
+    }
+
+    /// The WGSL declaration of the struct.
+    pub fn declaration(&self) -> String {
+        let mut declaration = format!("struct {} {{\n", self.name);
+        match self.usage {
+            Usage::Vertex { .. } => {
+                for location in self.vertex_locations() {
+                    declaration += &format!("    @location({}) {}: {},\n", location.location, location.name, location.ty);
+                }
+            }
+            Usage::Uniform | Usage::Storage => {
+                for field in &self.fields {
+                    declaration += &format!("    {}: {},\n", field.name, field.ty);
+                }
+            }
+        }
+        declaration + "}\n"
+    }
+
+    /// Checks that the WGSL struct is valid for its usage, and that its layout
+    /// matches the Rust struct: the same field offsets & size for uniform and
+    /// storage structs.
+    pub fn check_layout(&self) -> Result<(), String> {
+        let usage = match self.usage {
+            Usage::Uniform => format!("@group(0) @binding(0) var<uniform> value: {};\n", self.name),
+            Usage::Storage => format!("@group(0) @binding(0) var<storage> value: {};\n", self.name),
+            Usage::Vertex { .. } => format!(
+                "@vertex fn main(value: {}) -> @builtin(position) vec4<f32> {{ return vec4<f32>(0.0); }}\n",
+                self.name
+            ),
+        };
+        let source = self.declaration() + &usage;
+        let module = naga::front::wgsl::parse_str(&source).map_err(|e| format!("{}: {}", self.name, e.message()))?;
+        naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::all())
+            .validate(&module)
+            .map_err(|e| format!("{}: {}", self.name, e.as_inner()))?;
+
+        if let Usage::Vertex { .. } = self.usage {
+            // vertex attributes are read at the offsets of the vertex buffer
+            // layout, they have no layout rules
+            return Ok(());
+        }
+
+        let mut layouter = naga::proc::Layouter::default();
+        layouter.update(module.to_ctx()).map_err(|e| format!("{}: {}", self.name, e))?;
+        let Some((_, ty)) = module.types.iter().find(|(_, ty)| ty.name.as_deref() == Some(self.name)) else {
+            return Err(format!("{}: struct not found", self.name));
+        };
+        let naga::TypeInner::Struct { members, span } = &ty.inner else {
+            return Err(format!("{}: not a struct", self.name));
+        };
+        for (field, member) in self.fields.iter().zip(members) {
+            if field.offset != member.offset as usize {
+                return Err(format!(
+                    "{}.{}: at offset {} in Rust, {} in WGSL",
+                    self.name, field.name, field.offset, member.offset
+                ));
+            }
+        }
+        if self.size != *span as usize {
+            return Err(format!("{}: {} bytes in Rust, {} in WGSL", self.name, self.size, span));
+        }
+        Ok(())
+    }
+}
+
+/// The Rust types shared with the shaders.
+pub fn registered() -> Vec<StructInfo> {
+    vec![
+        StructInfo::of::<CameraUniform>(),
+        StructInfo::of::<LightUniform>(),
+        StructInfo::of::<GlobalUniforms>(),
+        StructInfo::of::<MaterialUniform>(),
+        StructInfo::of::<ModelVertex>(),
+        StructInfo::of::<InstanceRaw>(),
+    ]
+}
+
+/// The contents of [`FILE`], the declarations of the registered structs.
+pub fn source() -> String {
+    let mut source = "#pragma once\n\n// Generated from the Rust types in src/shaders/structs.rs\n".to_string();
+    for info in registered() {
+        source += "\n";
+        source += &info.declaration();
+    }
+    source
+}
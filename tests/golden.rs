@@ -0,0 +1,161 @@
+//! Golden image tests: renders fixed scenes offscreen and compares the result
+//! with the reference images in `tests/golden`.
+//!
+//! Run with `UPDATE_GOLDEN=1` to write new references after an intentional
+//! change in rendering. Mismatches write the actual image and a diff image to
+//! `target/tmp/golden`.
+
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use image::{Rgba, RgbaImage};
+use wgpu_renderer::core::resources::AssetSource;
+use wgpu_renderer::core::scene::Scene;
+use wgpu_renderer::core::settings::Settings;
+use wgpu_renderer::core::state::State;
+
+const WIDTH: u32 = 256;
+const HEIGHT: u32 = 256;
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+/// Largest per-channel difference that still counts as a matching pixel.
+const CHANNEL_TOLERANCE: u8 = 8;
+/// Fraction of pixels allowed to differ by more than `CHANNEL_TOLERANCE`.
+const MAX_MISMATCH_RATIO: f32 = 0.005;
+
+// Only one device at a time, software adapters don't like sharing.
+static RENDER_LOCK: Mutex<()> = Mutex::new(());
+
+struct Shot {
+    name: &'static str,
+    scene: &'static str,
+    camera_position: [f32; 3],
+    camera_yaw: f32,
+    camera_pitch: f32,
+    time: f32,
+}
+
+/// Renders a shot. Fails without an adapter, a software one (llvmpipe,
+/// SwiftShader, WARP) is enough.
+fn render(shot: &Shot) -> RgbaImage {
+    let _lock = RENDER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+    let scene = pollster::block_on(Scene::load(&AssetSource::Embedded, shot.scene)).expect("golden: can't load scene");
+    let mut state = pollster::block_on(State::new_headless(WIDTH, HEIGHT, FORMAT, &scene, &Settings::default()))
+        .unwrap_or_else(|e| panic!("golden: can't render {}, no adapter: {e:?}", shot.name));
+
+    state.set_camera(shot.camera_position.into(), shot.camera_yaw, shot.camera_pitch);
+    state.update(Duration::ZERO, Duration::from_secs_f32(shot.time));
+    state.render().expect("golden: render failed");
+
+    let capture = state.capture_color().expect("golden: capture failed");
+    capture.to_image().expect("golden: unsupported capture format").to_rgba8()
+}
+
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden")
+}
+
+fn output_dir() -> PathBuf {
+    Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden")
+}
+
+/// Compares `actual` with the reference image for `name`, writing the actual
+/// and diff images on mismatch.
+fn assert_golden(name: &str, actual: &RgbaImage) {
+    let reference_path = golden_dir().join(format!("{name}.png"));
+
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all(golden_dir()).unwrap();
+        actual.save(&reference_path).unwrap();
+        eprintln!("golden: updated {}", reference_path.display());
+        return;
+    }
+
+    let expected = match image::open(&reference_path) {
+        Ok(image) => image.to_rgba8(),
+        Err(e) => panic!(
+            "golden: can't open reference {} ({e}), run with UPDATE_GOLDEN=1 to create it",
+            reference_path.display()
+        ),
+    };
+    assert_eq!(
+        expected.dimensions(),
+        actual.dimensions(),
+        "golden: {name} size differs from reference"
+    );
+
+    let mut diff = RgbaImage::new(actual.width(), actual.height());
+    let mut mismatches = 0;
+    let mut max_difference = 0;
+    for ((a, e), d) in actual.pixels().zip(expected.pixels()).zip(diff.pixels_mut()) {
+        let difference = a.0.iter().zip(e.0.iter()).map(|(a, e)| a.abs_diff(*e)).max().unwrap_or(0);
+        max_difference = max_difference.max(difference);
+        *d = if difference > CHANNEL_TOLERANCE {
+            mismatches += 1;
+            Rgba([255, 0, 0, 255])
+        } else {
+            // dimmed grayscale of the reference for context
+            let luma = (e.0[0] as u32 + e.0[1] as u32 + e.0[2] as u32) / 12;
+            Rgba([luma as u8, luma as u8, luma as u8, 255])
+        };
+    }
+
+    let ratio = mismatches as f32 / (actual.width() * actual.height()) as f32;
+    if ratio > MAX_MISMATCH_RATIO {
+        let output = output_dir();
+        std::fs::create_dir_all(&output).unwrap();
+        let actual_path = output.join(format!("{name}_actual.png"));
+        let diff_path = output.join(format!("{name}_diff.png"));
+        actual.save(&actual_path).unwrap();
+        diff.save(&diff_path).unwrap();
+        panic!(
+            "golden: {name} differs from reference in {:.2}% of pixels (max channel difference {max_difference}), see {} and {}",
+            ratio * 100.0,
+            actual_path.display(),
+            diff_path.display(),
+        );
+    }
+}
+
+fn check(shot: Shot) {
+    let actual = render(&shot);
+    assert_golden(shot.name, &actual);
+}
+
+#[test]
+fn cube_front() {
+    check(Shot {
+        name: "cube_front",
+        scene: "scenes/cube.ron",
+        camera_position: [54.0, 2.5, 35.0],
+        camera_yaw: 0.0,
+        camera_pitch: -20.0,
+        time: 2.0,
+    });
+}
+
+#[test]
+fn cube_corner() {
+    check(Shot {
+        name: "cube_corner",
+        scene: "scenes/cube.ron",
+        camera_position: [55.0, 4.0, 30.0],
+        camera_yaw: 45.0,
+        camera_pitch: -30.0,
+        time: 5.0,
+    });
+}
+
+#[test]
+fn fog_above() {
+    check(Shot {
+        name: "fog_above",
+        scene: "scenes/cube.ron",
+        camera_position: [20.0, 80.0, 35.0],
+        camera_yaw: 0.0,
+        camera_pitch: -60.0,
+        time: 2.0,
+    });
+}
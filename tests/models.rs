@@ -0,0 +1,75 @@
+//! Model loading tests: renders models from `tests/assets` that have caused
+//! problems before.
+
+use std::path::Path;
+use std::time::Duration;
+
+use wgpu_renderer::core::resources::AssetSource;
+use wgpu_renderer::core::scene::Scene;
+use wgpu_renderer::core::settings::Settings;
+use wgpu_renderer::core::state::State;
+
+/// Renders `model` from `tests/assets` with the camera of
+/// [`Scene::from_model`], looking along +x from 10 units away at a height of
+/// 2. A unit is about 6 pixels.
+fn render_model(model: &str) -> image::RgbaImage {
+    let settings = Settings {
+        assets: AssetSource::Directory(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("assets")),
+        ..Settings::default()
+    };
+    let scene = Scene::from_model(model);
+    let mut state = pollster::block_on(State::new_headless(64, 64, wgpu::TextureFormat::Rgba8UnormSrgb, &scene, &settings))
+        .unwrap_or_else(|e| panic!("models: can't render {model}: {e:?}"));
+
+    state.update(Duration::ZERO, Duration::ZERO);
+    state.render().expect("models: render failed");
+
+    let image = state.capture_color().expect("models: capture failed").to_image().expect("models: unsupported capture format");
+    image.to_rgba8()
+}
+
+/// Whether `pixel` is mostly `channel`, like a lit red, green or blue surface.
+fn is_mostly(pixel: [u8; 4], channel: usize) -> bool {
+    (0..3).all(|other| other == channel || pixel[channel] > pixel[other].saturating_add(32))
+}
+
+/// Whether `pixel` is lit and gray, like the white default material.
+fn is_gray(pixel: [u8; 4]) -> bool {
+    pixel[0] > 32 && pixel[0].abs_diff(pixel[1]) < 16 && pixel[0].abs_diff(pixel[2]) < 16
+}
+
+#[test]
+fn gltf_without_materials_uses_default_material() {
+    // no `materials` in the file, the triangle covers the pixel if it's drawn
+    // with a default one
+    let pixel = render_model("models/Triangle.gltf").get_pixel(32, 40).0;
+    assert_ne!(pixel, [0, 0, 0, 255], "models: the triangle wasn't drawn");
+}
+
+#[test]
+fn gltf_textures_use_their_texcoord_set() {
+    // the base color texture is green on the left and red on the right, set 0
+    // maps to the left and set 1, the one the texture uses, to the right
+    let pixel = render_model("models/TexCoordSet.gltf").get_pixel(32, 40).0;
+    assert!(is_mostly(pixel, 0), "models: triangle is {:?}", pixel);
+}
+
+#[test]
+fn obj_materials_and_fallbacks() {
+    let image = render_model("models/Quads.obj");
+    let pixel = |x, y| image.get_pixel(x, y).0;
+
+    // the missing map_Kd leaves the diffuse color
+    assert!(is_mostly(pixel(20, 20), 0), "models: red quad is {:?}", pixel(20, 20));
+    assert!(is_mostly(pixel(44, 20), 1), "models: green quad is {:?}", pixel(44, 20));
+    // usemtl of a material that isn't in the MTL file
+    assert!(is_gray(pixel(20, 44)), "models: quad without material is {:?}", pixel(20, 44));
+    // no normals in the file, lit with flat ones
+    assert!(is_mostly(pixel(44, 44), 2), "models: blue quad is {:?}", pixel(44, 44));
+}
+
+#[test]
+fn obj_without_mtl_file_uses_default_material() {
+    let pixel = render_model("models/NoMaterials.obj").get_pixel(32, 44).0;
+    assert!(is_gray(pixel), "models: quad is {:?}", pixel);
+}
@@ -0,0 +1,289 @@
+//! Shader validation: runs every top-level shader in `res/shaders` through the
+//! preprocessor and naga, with every combination of its defines, and checks
+//! the bindings it uses against the bind group layouts the renderer creates.
+//! The structs generated from the Rust types are checked against the Rust
+//! layouts. A broken shader fails here instead of when the renderer starts.
+
+use std::collections::HashSet;
+use std::path::Path;
+
+use naga::{AddressSpace, ImageClass, ScalarKind, ShaderStage, TypeInner};
+use wgpu::{BindGroupLayoutEntry, BindingType, BufferBindingType, SamplerBindingType, ShaderStages, TextureSampleType};
+use wgpu_renderer::core::instance::InstanceRaw;
+use wgpu_renderer::core::layouts::{self, Group};
+use wgpu_renderer::core::model::{ModelVertex, Vertex};
+use wgpu_renderer::core::settings::ShaderSettings;
+use wgpu_renderer::shaders::preprocessor::{preprocess_wgsl, PreprocessError, PreprocessedShader};
+use wgpu_renderer::shaders::structs::{self, StructInfo};
+
+struct Shader {
+    name: &'static str,
+    /// The defines the renderer builds variants with, all combinations are
+    /// checked.
+    defines: &'static [&'static str],
+    /// The bind group layouts of the passes using the shader, by group.
+    groups: Vec<Vec<BindGroupLayoutEntry>>,
+}
+
+impl Shader {
+    fn new(name: &'static str, defines: &'static [&'static str], groups: &[Group]) -> Self {
+        Self {
+            name,
+            defines,
+            groups: groups.iter().map(|group| group.entries()).collect(),
+        }
+    }
+}
+
+fn shaders() -> Vec<Shader> {
+    vec![
+        Shader::new("pbr.wgsl", &["SHADOWS", "WEBGL", "ALPHA_MASK", "ALPHA_BLEND"], &layouts::pbr_groups()),
+        Shader::new("depth.wgsl", &["ALPHA_MASK"], &layouts::depth_groups()),
+        Shader::new("light_debug.wgsl", &[], &layouts::light_debug_groups()),
+        Shader::new("fog.wgsl", &["SHADOWS"], &layouts::fog_groups()),
+        Shader::new("mipmap.wgsl", &[], &layouts::mipmap_groups()),
+    ]
+}
+
+/// Every subset of `defines`.
+fn variants(defines: &[&'static str]) -> Vec<Vec<(&'static str, &'static str)>> {
+    (0..1usize << defines.len())
+        .map(|mask| {
+            defines
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| mask & (1 << i) != 0)
+                .map(|(_, define)| (*define, ""))
+                .collect()
+        })
+        .collect()
+}
+
+fn describe(name: &str, defines: &[(&str, &str)]) -> String {
+    if defines.is_empty() {
+        name.to_string()
+    } else {
+        let defines = defines.iter().map(|(define, _)| *define).collect::<Vec<_>>();
+        format!("{} [{}]", name, defines.join(", "))
+    }
+}
+
+fn stage_flags(stage: ShaderStage) -> ShaderStages {
+    match stage {
+        ShaderStage::Vertex => ShaderStages::VERTEX,
+        ShaderStage::Fragment => ShaderStages::FRAGMENT,
+        ShaderStage::Compute => ShaderStages::COMPUTE,
+    }
+}
+
+/// Whether a global of the shader can be bound with a layout entry, following
+/// the checks wgpu does when creating the pipeline.
+fn compatible(module: &naga::Module, var: &naga::GlobalVariable, ty: &BindingType) -> Result<(), String> {
+    let inner = &module.types[var.ty].inner;
+    match (inner, ty) {
+        (TypeInner::Image { dim, arrayed, class }, BindingType::Texture { sample_type, view_dimension, multisampled }) => {
+            let dimension = match (dim, arrayed) {
+                (naga::ImageDimension::D1, false) => wgpu::TextureViewDimension::D1,
+                (naga::ImageDimension::D2, false) => wgpu::TextureViewDimension::D2,
+                (naga::ImageDimension::D2, true) => wgpu::TextureViewDimension::D2Array,
+                (naga::ImageDimension::D3, false) => wgpu::TextureViewDimension::D3,
+                (naga::ImageDimension::Cube, false) => wgpu::TextureViewDimension::Cube,
+                (naga::ImageDimension::Cube, true) => wgpu::TextureViewDimension::CubeArray,
+                _ => return Err(format!("unsupported image {:?}", inner)),
+            };
+            if dimension != *view_dimension {
+                return Err(format!("shader has a {:?} texture, layout has {:?}", dimension, view_dimension));
+            }
+            let (sample_types_match, multi) = match *class {
+                ImageClass::Sampled { kind, multi } => (
+                    matches!(
+                        (kind, sample_type),
+                        (ScalarKind::Float, TextureSampleType::Float { .. })
+                            | (ScalarKind::Sint, TextureSampleType::Sint)
+                            | (ScalarKind::Uint, TextureSampleType::Uint)
+                    ),
+                    multi,
+                ),
+                ImageClass::Depth { multi } => (*sample_type == TextureSampleType::Depth, multi),
+                ImageClass::Storage { .. } => return Err("storage textures aren't in any layout".to_string()),
+            };
+            if !sample_types_match {
+                return Err(format!("shader samples {:?}, layout has {:?}", class, sample_type));
+            }
+            if multi != *multisampled {
+                return Err(format!("shader multisampled {}, layout multisampled {}", multi, multisampled));
+            }
+            Ok(())
+        }
+        (TypeInner::Sampler { comparison }, BindingType::Sampler(sampler)) => {
+            if *comparison != (*sampler == SamplerBindingType::Comparison) {
+                return Err(format!("shader comparison sampler {}, layout has {:?}", comparison, sampler));
+            }
+            Ok(())
+        }
+        (_, BindingType::Buffer { ty: BufferBindingType::Uniform, min_binding_size, .. }) if var.space == AddressSpace::Uniform => {
+            // wgpu rejects buffers smaller than the struct the shader reads
+            let size = inner.size(module.to_ctx()) as u64;
+            match min_binding_size {
+                Some(min) if min.get() < size => Err(format!(
+                    "shader reads {} bytes, layout has a minimum of {} bytes",
+                    size,
+                    min.get()
+                )),
+                _ => Ok(()),
+            }
+        }
+        _ => Err(format!("{:?} in {:?} can't be bound as {:?}", inner, var.space, ty)),
+    }
+}
+
+/// Preprocesses a shader variant like the renderer does, with the defines of
+/// the default shader settings.
+fn preprocess(name: &str, variant: &[(&str, &str)]) -> Result<PreprocessedShader, PreprocessError> {
+    let settings = ShaderSettings::default().defines();
+    let defines = settings
+        .iter()
+        .map(|(name, value)| (*name, value.as_str()))
+        .chain(variant.iter().copied())
+        .collect::<Vec<_>>();
+    preprocess_wgsl(name, &defines)
+}
+
+/// Validates a shader variant, returning the errors found.
+fn check(shader: &Shader, variant: &[(&str, &str)]) -> Vec<String> {
+    let preprocessed = match preprocess(shader.name, variant) {
+        Ok(preprocessed) => preprocessed,
+        Err(e) => return vec![e.to_string()],
+    };
+    let (module, info) = match preprocessed.validate() {
+        Ok(validated) => validated,
+        Err(e) => return vec![e],
+    };
+
+    let mut errors = Vec::new();
+    for (name, stage) in [("vs_main", ShaderStage::Vertex), ("fs_main", ShaderStage::Fragment)] {
+        if !module.entry_points.iter().any(|e| e.name == name && e.stage == stage) {
+            errors.push(format!("missing {:?} entry point {}", stage, name));
+        }
+    }
+
+    for (handle, var) in module.global_variables.iter() {
+        let Some(binding) = &var.binding else {
+            continue;
+        };
+        // unused globals aren't part of the pipeline layout
+        let stages = module
+            .entry_points
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !info.get_entry_point(*i)[handle].is_empty())
+            .fold(ShaderStages::NONE, |stages, (_, e)| stages | stage_flags(e.stage));
+        if stages.is_empty() {
+            continue;
+        }
+
+        let name = var.name.as_deref().unwrap_or("?");
+        let location = format!("{} (group {}, binding {})", name, binding.group, binding.binding);
+        let entry = shader
+            .groups
+            .get(binding.group as usize)
+            .and_then(|group| group.iter().find(|e| e.binding == binding.binding));
+        let Some(entry) = entry else {
+            errors.push(format!("{} isn't in the bind group layouts", location));
+            continue;
+        };
+        if !entry.visibility.contains(stages) {
+            errors.push(format!("{} is used in {:?}, layout is visible to {:?}", location, stages, entry.visibility));
+        }
+        if let Err(e) = compatible(&module, var, &entry.ty) {
+            errors.push(format!("{}: {}", location, e));
+        }
+    }
+    errors
+}
+
+#[test]
+fn shaders_validate_against_layouts() {
+    let mut failures = Vec::new();
+    for shader in shaders() {
+        for defines in variants(shader.defines) {
+            for error in check(&shader, &defines) {
+                failures.push(format!("{}: {}", describe(shader.name, &defines), error));
+            }
+        }
+    }
+    assert!(failures.is_empty(), "shader errors:\n{}", failures.join("\n"));
+}
+
+/// The WGSL type of a vertex attribute format.
+fn vertex_format_type(format: wgpu::VertexFormat) -> &'static str {
+    match format {
+        wgpu::VertexFormat::Float32 => "f32",
+        wgpu::VertexFormat::Float32x2 => "vec2<f32>",
+        wgpu::VertexFormat::Float32x3 => "vec3<f32>",
+        wgpu::VertexFormat::Float32x4 => "vec4<f32>",
+        wgpu::VertexFormat::Uint32 => "u32",
+        wgpu::VertexFormat::Uint32x2 => "vec2<u32>",
+        wgpu::VertexFormat::Uint32x3 => "vec3<u32>",
+        wgpu::VertexFormat::Uint32x4 => "vec4<u32>",
+        _ => "?",
+    }
+}
+
+#[test]
+fn shared_structs_match_wgsl_layouts() {
+    let failures = structs::registered()
+        .iter()
+        .filter_map(|info| info.check_layout().err())
+        .collect::<Vec<_>>();
+    assert!(failures.is_empty(), "struct layout errors:\n{}", failures.join("\n"));
+}
+
+#[test]
+fn vertex_structs_match_buffer_layouts() {
+    let vertex_structs = [
+        (StructInfo::of::<ModelVertex>(), ModelVertex::desc()),
+        (StructInfo::of::<InstanceRaw>(), InstanceRaw::desc()),
+    ];
+    for (info, desc) in vertex_structs {
+        assert_eq!(desc.array_stride as usize, info.size, "{}: stride isn't the size of the struct", info.name);
+        let locations = info.vertex_locations();
+        assert_eq!(desc.attributes.len(), locations.len(), "{}: attribute count", info.name);
+        for location in locations {
+            let attribute = desc
+                .attributes
+                .iter()
+                .find(|a| a.shader_location == location.location)
+                .unwrap_or_else(|| panic!("{}.{}: no attribute at location {}", info.name, location.name, location.location));
+            assert_eq!(attribute.offset as usize, location.offset, "{}.{}: offset", info.name, location.name);
+            assert_eq!(vertex_format_type(attribute.format), location.ty, "{}.{}: format", info.name, location.name);
+        }
+    }
+}
+
+#[test]
+fn all_shaders_are_checked() {
+    // included files are checked as part of the shaders including them
+    let mut checked = HashSet::new();
+    for shader in shaders() {
+        for defines in variants(shader.defines) {
+            let preprocessed = preprocess(shader.name, &defines).expect("shaders: can't preprocess");
+            checked.extend(preprocessed.files);
+        }
+    }
+
+    let shader_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("res/shaders");
+    let mut unchecked = Vec::new();
+    for entry in std::fs::read_dir(shader_dir).expect("shaders: can't list res/shaders") {
+        let name = entry.expect("shaders: can't list res/shaders").file_name().to_string_lossy().into_owned();
+        if name.ends_with(".wgsl") && !checked.contains(&name) {
+            unchecked.push(name);
+        }
+    }
+    unchecked.sort();
+    assert!(
+        unchecked.is_empty(),
+        "shaders not used by any checked shader, add them to shaders(): {}",
+        unchecked.join(", ")
+    );
+}